@@ -1,10 +1,30 @@
+use std::env;
+use std::fmt;
 use std::fs;
 
+#[derive(Clone, Copy, Debug)]
 enum Instruction {
     Noop,
     Addx(i32),
 }
 
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Noop => write!(f, "noop"),
+            Instruction::Addx(i) => write!(f, "addx {}", i),
+        }
+    }
+}
+
+// one entry per clock cycle: the instruction being executed, and the value of X during and after
+// the cycle
+struct Cycle {
+    instruction: Instruction,
+    x_before: i32,
+    x_after: i32,
+}
+
 fn decode(line: &str) -> Instruction {
     if line.starts_with("noop") {
         Instruction::Noop
@@ -18,22 +38,28 @@ fn decode(line: &str) -> Instruction {
     }
 }
 
-fn simulate_processor(instructions: Vec<Instruction>) -> Vec<i32> {
-    let mut state: Vec<i32> = vec![];
+fn trace_processor(instructions: &[Instruction]) -> Vec<Cycle> {
+    let mut cycles: Vec<Cycle> = vec![];
     let mut x = 1;
 
-    for instruction in instructions {
-        state.push(x);
+    for &instruction in instructions {
         match instruction {
-            Instruction::Noop => {},
+            Instruction::Noop => {
+                cycles.push(Cycle { instruction, x_before: x, x_after: x });
+            },
             Instruction::Addx(i) => {
-                state.push(x);
+                cycles.push(Cycle { instruction, x_before: x, x_after: x });
+                cycles.push(Cycle { instruction, x_before: x, x_after: x + i });
                 x += i;
             },
         }
     }
 
-    state
+    cycles
+}
+
+fn simulate_processor(instructions: &[Instruction]) -> Vec<i32> {
+    trace_processor(instructions).iter().map(|cycle| cycle.x_before).collect()
 }
 
 fn compute_signal_strength(state: &Vec<i32>) -> i32 {
@@ -52,26 +78,46 @@ fn compute_signal_strength(state: &Vec<i32>) -> i32 {
 const NROWS: usize = 6;
 const NCOLS: usize = 40;
 
-fn scan_display(state: &Vec<i32>) -> [[char; NCOLS]; NROWS] {
+// the CRT draws pixel c during cycle c+1; it lights up if the 3 pixel wide sprite centered on X
+// covers the pixel's column
+fn pixel_position(c: usize) -> (usize, usize) {
+    let row = c / NCOLS;
+    (row, c - NCOLS * row)
+}
+
+fn is_lit(c: usize, x: i32) -> bool {
+    let (_, col) = pixel_position(c);
+    (x - col as i32).abs() <= 1
+}
+
+fn scan_display(state: &[i32]) -> [[char; NCOLS]; NROWS] {
     let mut display: [[char; NCOLS]; NROWS] = [['.'; NCOLS]; NROWS];
-    for (c, &x) in state.iter().enumerate() {
-        let row = c / NCOLS;
-        let col = c - NCOLS * row;
-        let sprite = [x - 1, x, x + 1];
-        for pixel in sprite {
-            if pixel < 0 || pixel >= NCOLS.try_into().unwrap() { continue; }
-            if pixel == col.try_into().unwrap() {
-                display[row][col] = '#';
-            }
+    for (c, &x) in state.iter().enumerate().take(NROWS * NCOLS) {
+        let (row, col) = pixel_position(c);
+        if is_lit(c, x) {
+            display[row][col] = '#';
         }
     }
     display
 }
 
+fn print_trace(cycles: &[Cycle]) {
+    println!("{:>5} | {:<10} | {:>8} | {:>7} | {:>7} | lit", "cycle", "instr", "x before", "x after", "pixel");
+    for (c, cycle) in cycles.iter().enumerate() {
+        let (row, col) = pixel_position(c);
+        let lit = if is_lit(c, cycle.x_before) { '#' } else { '.' };
+        println!("{:>5} | {:<10} | {:>8} | {:>7} | {:>7} | {}",
+                 c + 1, cycle.instruction.to_string(), cycle.x_before, cycle.x_after, format!("{},{}", row, col), lit);
+    }
+}
+
 pub fn solve() {
     let input = fs::read_to_string("resources/day10.txt").unwrap();
     let instructions: Vec<Instruction> = input.lines().map(decode).collect();
-    let state = simulate_processor(instructions);
+    if env::args().any(|arg| arg == "--trace") {
+        print_trace(&trace_processor(&instructions));
+    }
+    let state = simulate_processor(&instructions);
     let signal_strength = compute_signal_strength(&state);
     println!("{}", signal_strength);
 
@@ -83,3 +129,24 @@ pub fn solve() {
         print!("\n");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_processor() {
+        let instructions: Vec<Instruction> = ["noop", "addx 3", "addx -5"].into_iter().map(decode).collect();
+        let cycles = trace_processor(&instructions);
+        assert_eq!(cycles.len(), 5);
+        let registers: Vec<(i32, i32)> = cycles.iter().map(|cycle| (cycle.x_before, cycle.x_after)).collect();
+        assert_eq!(registers, vec![(1, 1), (1, 1), (1, 4), (4, 4), (4, -1)]);
+        let names: Vec<String> = cycles.iter().map(|cycle| cycle.instruction.to_string()).collect();
+        assert_eq!(names, vec!["noop", "addx 3", "addx 3", "addx -5", "addx -5"]);
+
+        let lit: Vec<bool> = cycles.iter().enumerate().map(|(c, cycle)| is_lit(c, cycle.x_before)).collect();
+        assert_eq!(lit, vec![true, true, true, true, true]);
+        assert!(!is_lit(5, -1));
+        assert!(is_lit(NCOLS, 1));
+    }
+}
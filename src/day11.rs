@@ -1,10 +1,186 @@
 use std::fs;
+use std::fmt;
+use std::collections::HashMap;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+// right hand side of the `new = ...` line, evaluated for each inspected item
+#[derive(Debug, PartialEq)]
 enum Operation {
-    Add(u64),
-    Multiply(u64),
-    Square,
-    Double,
+    Old,
+    Constant(u64),
+    Binary(Operator, Box<Operation>, Box<Operation>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+    Old,
+    Number(u64),
+    Operator(Operator),
+    Open,
+    Close,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut iter = expression.char_indices().peekable();
+    while let Some(&(i, c)) = iter.peek() {
+        match c {
+            ' ' => { iter.next(); },
+            '+' => { iter.next(); tokens.push(Token::Operator(Operator::Add)); },
+            '-' => { iter.next(); tokens.push(Token::Operator(Operator::Subtract)); },
+            '*' => { iter.next(); tokens.push(Token::Operator(Operator::Multiply)); },
+            '/' => { iter.next(); tokens.push(Token::Operator(Operator::Divide)); },
+            '(' => { iter.next(); tokens.push(Token::Open); },
+            ')' => { iter.next(); tokens.push(Token::Close); },
+            '0'..='9' => {
+                let mut n: u64 = 0;
+                while let Some(&(_, d)) = iter.peek() {
+                    match d.to_digit(10) {
+                        Some(digit) => {
+                            n = n.checked_mul(10).and_then(|n| n.checked_add(digit as u64))
+                                .ok_or(format!("constant too large at column {}", i))?;
+                            iter.next();
+                        },
+                        None => break,
+                    }
+                }
+                tokens.push(Token::Number(n));
+            },
+            _ if expression[i..].starts_with("old") => {
+                iter.nth(2);
+                tokens.push(Token::Old);
+            },
+            _ => return Err(format!("unexpected character '{}' at column {}", c, i)),
+        }
+    }
+    Ok(tokens)
+}
+
+// expression := term (('+' | '-') term)*
+// term       := factor (('*' | '/') factor)*
+// factor     := 'old' | number | '(' expression ')'
+fn parse_expression(tokens: &[Token], pos: &mut usize) -> Result<Operation, String> {
+    let mut lhs = parse_term(tokens, pos)?;
+    while let Some(Token::Operator(op @ (Operator::Add | Operator::Subtract))) = tokens.get(*pos) {
+        *pos += 1;
+        let rhs = parse_term(tokens, pos)?;
+        lhs = Operation::Binary(*op, Box::new(lhs), Box::new(rhs));
+    }
+    Ok(lhs)
+}
+
+fn parse_term(tokens: &[Token], pos: &mut usize) -> Result<Operation, String> {
+    let mut lhs = parse_factor(tokens, pos)?;
+    while let Some(Token::Operator(op @ (Operator::Multiply | Operator::Divide))) = tokens.get(*pos) {
+        *pos += 1;
+        let rhs = parse_factor(tokens, pos)?;
+        if *op == Operator::Divide && rhs == Operation::Constant(0) {
+            return Err(String::from("division by zero"));
+        }
+        lhs = Operation::Binary(*op, Box::new(lhs), Box::new(rhs));
+    }
+    Ok(lhs)
+}
+
+fn parse_factor(tokens: &[Token], pos: &mut usize) -> Result<Operation, String> {
+    let token = tokens.get(*pos).ok_or("unexpected end of expression")?;
+    *pos += 1;
+    match token {
+        Token::Old => Ok(Operation::Old),
+        Token::Number(n) => Ok(Operation::Constant(*n)),
+        Token::Open => {
+            let inner = parse_expression(tokens, pos)?;
+            match tokens.get(*pos) {
+                Some(Token::Close) => {
+                    *pos += 1;
+                    Ok(inner)
+                },
+                _ => Err(String::from("unbalanced parenthesis")),
+            }
+        },
+        t => Err(format!("unexpected token {:?}", t)),
+    }
+}
+
+fn parse_operation(expression: &str) -> Result<Operation, String> {
+    let tokens = tokenize(expression)?;
+    let mut pos = 0;
+    let operation = parse_expression(&tokens, &mut pos)?;
+    if pos != tokens.len() {
+        return Err(format!("trailing token {:?}", tokens[pos]));
+    }
+    Ok(operation)
+}
+
+impl Operator {
+    fn symbol(&self) -> char {
+        match self {
+            Operator::Add => '+',
+            Operator::Subtract => '-',
+            Operator::Multiply => '*',
+            Operator::Divide => '/',
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::Add | Operator::Subtract => 0,
+            Operator::Multiply | Operator::Divide => 1,
+        }
+    }
+}
+
+impl Operation {
+    fn precedence(&self) -> u8 {
+        match self {
+            Operation::Binary(op, _, _) => op.precedence(),
+            _ => u8::MAX,
+        }
+    }
+}
+
+// same syntax as the input, with parentheses only where the grammar needs them
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Old => write!(f, "old"),
+            Operation::Constant(constant) => write!(f, "{}", constant),
+            Operation::Binary(op, lhs, rhs) => {
+                // operators are left associative, so a right operand of the same precedence
+                // needs parentheses too
+                if lhs.precedence() < op.precedence() {
+                    write!(f, "({})", lhs)?;
+                } else {
+                    write!(f, "{}", lhs)?;
+                }
+                write!(f, " {} ", op.symbol())?;
+                if rhs.precedence() <= op.precedence() {
+                    write!(f, "({})", rhs)
+                } else {
+                    write!(f, "{}", rhs)
+                }
+            },
+        }
+    }
+}
+
+impl Operation {
+    // division does not distribute over the modulus, so expressions containing it have to be
+    // evaluated on the actual worry levels
+    fn is_modular(&self) -> bool {
+        match self {
+            Operation::Old | Operation::Constant(_) => true,
+            Operation::Binary(Operator::Divide, _, _) => false,
+            Operation::Binary(_, lhs, rhs) => lhs.is_modular() && rhs.is_modular(),
+        }
+    }
 }
 
 struct Monkey {
@@ -16,110 +192,302 @@ struct Monkey {
 
 type Inventory = Vec<u64>;
 
-fn parse(input: String) -> (Vec<Inventory>, Vec<Monkey>) {
-    let mut iter = input.lines();
-    let mut inventories: Vec<Inventory> = vec![];
-    let mut monkeys: Vec<Monkey> = vec![];
+fn parse_field<T>(value: &str, prefix: &str) -> Result<T, String> where T: std::str::FromStr {
+    value.strip_prefix(prefix)
+        .ok_or(format!("expected '{}', got '{}'", prefix, value))?
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid number in '{}'", value))
+}
 
-    loop {
-        iter.next();
-
-        let mut items_iter = iter.next().unwrap().split(": ");
-        items_iter.next();
-        let items: Vec<u64> = items_iter.next().unwrap().split(", ")
-            .map(|item| u64::from_str_radix(item, 10).unwrap())
-            .collect();
-
-        let operation_str = iter.next().unwrap();
-        let operation = if operation_str.contains('*') {
-            let mut operation_iter = operation_str.split(" * ");
-            operation_iter.next();
-            let term_str = operation_iter.next().unwrap();
-            if term_str == "old" {
-                Operation::Square
-            } else {
-                let constant = u64::from_str_radix(term_str, 10).unwrap();
-                Operation::Multiply(constant)
-            }
-        } else if operation_str.contains('+') {
-            let mut operation_iter = operation_str.split(" + ");
-            operation_iter.next();
-            let term_str = operation_iter.next().unwrap();
-            if term_str == "old" {
-                Operation::Double
-            } else {
-                let constant = u64::from_str_radix(term_str, 10).unwrap();
-                Operation::Add(constant)
-            }
-        } else {
-            panic!("unhandled operation");
-        };
+fn parse_monkey(block: &[&str], expected_id: usize) -> Result<(Inventory, Monkey), String> {
+    let header = block[0].trim();
+    let id: usize = header.strip_prefix("Monkey ")
+        .and_then(|rest| rest.strip_suffix(':'))
+        .and_then(|id| id.parse().ok())
+        .ok_or(format!("monkey {}: malformed header '{}'", expected_id, header))?;
+    if id != expected_id {
+        return Err(format!("monkey {}: expected monkey {} (ids must be contiguous)", id, expected_id));
+    }
 
-        let test_str = iter.next().unwrap();
-        let mut test_iter = test_str.split(" divisible by ");
-        test_iter.next();
-        let divisor = u64::from_str_radix(test_iter.next().unwrap(), 10).unwrap();
+    let mut items: Option<Inventory> = None;
+    let mut operation: Option<Operation> = None;
+    let mut divisor: Option<u64> = None;
+    let mut if_branch: Option<usize> = None;
+    let mut else_branch: Option<usize> = None;
 
-        let if_true_str = iter.next().unwrap();
-        let mut if_true_iter = if_true_str.split(" throw to monkey ");
-        if_true_iter.next();
-        let if_branch = usize::from_str_radix(if_true_iter.next().unwrap(), 10).unwrap();
+    let mut labels: Vec<&str> = vec![];
+    for line in &block[1..] {
+        let (label, value) = line.trim().split_once(':')
+            .ok_or(format!("monkey {}: unlabeled line '{}'", id, line.trim()))?;
+        let value = value.trim();
+        let field_error = |e: String| format!("monkey {}: field '{}': {}", id, label, e);
+        if labels.contains(&label) {
+            return Err(field_error(String::from("given more than once")));
+        }
+        labels.push(label);
+        match label {
+            "Starting items" => {
+                // no items at all is fine, an empty entry between commas isn't
+                let parsed: Result<Inventory, String> = value.split(',')
+                    .map(str::trim)
+                    .filter(|_| !value.is_empty())
+                    .map(|item| item.parse().map_err(|_| format!("invalid item '{}'", item)))
+                    .collect();
+                items = Some(parsed.map_err(field_error)?);
+            },
+            "Operation" => {
+                let expression = value.strip_prefix("new =")
+                    .ok_or(format!("expected 'new = ...', got '{}'", value))
+                    .and_then(parse_operation);
+                operation = Some(expression.map_err(field_error)?);
+            },
+            "Test" => {
+                let d: u64 = parse_field(value, "divisible by").map_err(field_error)?;
+                if d == 0 {
+                    return Err(field_error(String::from("divisor must be positive")));
+                }
+                divisor = Some(d);
+            },
+            "If true" => { if_branch = Some(parse_field(value, "throw to monkey").map_err(field_error)?); },
+            "If false" => { else_branch = Some(parse_field(value, "throw to monkey").map_err(field_error)?); },
+            _ => return Err(format!("monkey {}: unknown field '{}'", id, label)),
+        }
+    }
 
-        let if_false_str = iter.next().unwrap();
-        let mut if_false_iter = if_false_str.split(" throw to monkey ");
-        if_false_iter.next();
-        let else_branch = usize::from_str_radix(if_false_iter.next().unwrap(), 10).unwrap();
+    let missing = |field: &str| format!("monkey {}: missing field '{}'", id, field);
+    let monkey = Monkey {
+        operation: operation.ok_or_else(|| missing("Operation"))?,
+        divisor: divisor.ok_or_else(|| missing("Test"))?,
+        if_branch: if_branch.ok_or_else(|| missing("If true"))?,
+        else_branch: else_branch.ok_or_else(|| missing("If false"))?,
+    };
+    Ok((items.ok_or_else(|| missing("Starting items"))?, monkey))
+}
 
-        inventories.push(items);
+fn parse(input: &str) -> Result<(Vec<Inventory>, Vec<Monkey>), String> {
+    let lines: Vec<&str> = input.lines().collect();
+    let mut inventories: Vec<Inventory> = vec![];
+    let mut monkeys: Vec<Monkey> = vec![];
 
-        monkeys.push(Monkey {
-            operation: operation,
-            divisor: divisor,
-            if_branch: if_branch,
-            else_branch: else_branch,
-        });
+    for block in lines.split(|line| line.trim().is_empty()).filter(|block| !block.is_empty()) {
+        let (items, monkey) = parse_monkey(block, monkeys.len())?;
+        inventories.push(items);
+        monkeys.push(monkey);
+    }
 
-        match iter.next() {
-            Some(_) => { },
-            None => { break; },
+    for (id, monkey) in monkeys.iter().enumerate() {
+        for (field, target) in [("If true", monkey.if_branch), ("If false", monkey.else_branch)] {
+            if target >= monkeys.len() {
+                return Err(format!("monkey {}: field '{}': no monkey {}", id, field, target));
+            }
         }
     }
 
-    (inventories, monkeys)
+    Ok((inventories, monkeys))
 }
 
-fn compute_worry(operation: &Operation, item: u64) -> u64 {
+// when a modulus is given, every intermediate result is reduced by it; overflows, negative worry
+// levels and divisions by zero are errors
+fn compute_worry(operation: &Operation, item: u64, modulus: Option<u64>) -> Result<u64, String> {
+    let reduce = |worry: u64| match modulus {
+        Some(m) => worry % m,
+        None => worry,
+    };
     match operation {
-        Operation::Add(constant) => item + constant,
-        Operation::Multiply(constant) => item * constant,
-        Operation::Double => item + item,
-        Operation::Square => item * item,
+        Operation::Old => Ok(reduce(item)),
+        Operation::Constant(constant) => Ok(reduce(*constant)),
+        Operation::Binary(op, lhs, rhs) => {
+            let lhs = compute_worry(lhs, item, modulus)?;
+            let rhs = compute_worry(rhs, item, modulus)?;
+            let overflow = || format!("worry level overflow: {} {} {}", lhs, op.symbol(), rhs);
+            match (op, modulus) {
+                (Operator::Add, _) => lhs.checked_add(rhs).map(reduce).ok_or_else(overflow),
+                (Operator::Multiply, _) => lhs.checked_mul(rhs).map(reduce).ok_or_else(overflow),
+                (Operator::Subtract, Some(m)) => lhs.checked_add(m).map(|lhs| (lhs - rhs) % m).ok_or_else(overflow),
+                (Operator::Subtract, None) => lhs.checked_sub(rhs)
+                    .ok_or(format!("negative worry level: {} - {}", lhs, rhs)),
+                (Operator::Divide, _) => lhs.checked_div(rhs)
+                    .ok_or(format!("division by zero: {} / {}", lhs, rhs)),
+            }
+        },
+    }
+}
+
+fn operation_error(id: usize, monkey: &Monkey) -> impl Fn(String) -> String + '_ {
+    move |e| format!("monkey {}: operation 'new = {}': {}", id, monkey.operation, e)
+}
+
+// rounds after which count_inspections dumps the monkeys' state
+enum ReportRound {
+    At(u32),
+    Every(u32),
+}
+
+// comma separated list of round numbers and `every N` entries, e.g. "1,20,every 1000"
+fn parse_report_rounds(selection: &str) -> Result<Vec<ReportRound>, String> {
+    selection.split(',')
+        .map(str::trim)
+        .map(|entry| match entry.strip_prefix("every ") {
+            Some(period) => period.trim().parse().ok().filter(|&p| p > 0).map(ReportRound::Every),
+            None => entry.parse().ok().map(ReportRound::At),
+        }.ok_or(format!("invalid round selection: '{}'", entry)))
+        .collect()
+}
+
+fn is_reported(round: u32, report: &[ReportRound]) -> bool {
+    report.iter().any(|r| match *r {
+        ReportRound::At(at) => round == at,
+        ReportRound::Every(period) => round.is_multiple_of(period),
+    })
+}
+
+// same layout as the puzzle's walkthrough, with the run named in the header since both parts
+// report the same rounds
+fn print_report(run: &str, round: u32, inventories: &[Inventory], inspections: &[u64]) {
+    println!("== {}: After round {} ==", run, round);
+    for (id, inventory) in inventories.iter().enumerate() {
+        let items: Vec<String> = inventory.iter().map(|item| item.to_string()).collect();
+        println!("Monkey {}: {}", id, items.join(", "));
     }
+    for (id, count) in inspections.iter().enumerate() {
+        println!("Monkey {} inspected items {} times.", id, count);
+    }
+    println!();
 }
 
-fn count_inspections(rounds: u32, mut inventories: Vec<Inventory>, monkeys: &Vec<Monkey>, attenuation: Option<fn(u64) -> u64>) -> Vec<u64> {
+// without attenuation, worry levels are only kept in bounds by reducing them modulo supermod,
+// which division does not survive: such monkeys are refused rather than left to overflow. With
+// attenuation, reducing would change what it divides, so worry levels are kept exact.
+fn count_inspections(rounds: u32, mut inventories: Vec<Inventory>, monkeys: &[Monkey], attenuation: Option<fn(u64) -> u64>,
+                     report: &[ReportRound], run: &str) -> Result<Vec<u64>, String> {
     let n = inventories.len();
     let supermod: u64 = monkeys.iter().map(|m| m.divisor).product();
+    let modulus = match monkeys.iter().position(|m| !m.operation.is_modular()) {
+        _ if attenuation.is_some() => None,
+        None => Some(supermod),
+        Some(id) => return Err(operation_error(id, &monkeys[id])(
+            String::from("division cannot be combined with reducing worry levels"))),
+    };
 
     let mut inspections: Vec<u64> = vec![0; n];
-    for _ in 0..rounds {
+    for round in 1..=rounds {
         for id in 0..n {
             let monkey = &monkeys[id];
-            let changes: Vec<(usize, u64)> = inventories[id].iter().map(|&item| {
-                let worry = compute_worry(&monkey.operation, item) % supermod;
+            let mut changes: Vec<(usize, u64)> = vec![];
+            for &item in &inventories[id] {
+                let worry = compute_worry(&monkey.operation, item, modulus).map_err(operation_error(id, monkey))?;
                 let item = match attenuation {
                     Some(f) => f(worry),
                     _ => worry,
                 };
                 inspections[id] += 1;
                 let receiver_id = if item % monkey.divisor == 0 { monkey.if_branch } else { monkey.else_branch };
-                (receiver_id, item)
-            }).collect();
+                changes.push((receiver_id, item));
+            }
             inventories[id].clear();
             for change in changes {
                 inventories[change.0].push(change.1);
             }
         }
+        if is_reported(round, report) {
+            print_report(run, round, &inventories, &inspections);
+        }
+    }
+
+    inspections.sort_by(|a, b| b.cmp(a));
+
+    Ok(inspections)
+}
+
+// inspections suffered by a single item, round by round, until its (monkey, worry) state at the
+// start of a round repeats: from then on, rounds cycle_start.. repeat forever
+struct Trajectory {
+    rounds: Vec<Vec<usize>>,
+    cycle_start: usize,
+}
+
+impl Trajectory {
+    // monkeys that inspect the item during any round, however far out
+    fn round(&self, r: u64) -> &[usize] {
+        let len = self.rounds.len() as u64;
+        let start = self.cycle_start as u64;
+        let r = if r < len { r } else { start + (r - start) % (len - start) };
+        &self.rounds[r as usize]
+    }
+
+    fn inspections(&self, rounds: u64, n: usize) -> Vec<u64> {
+        let mut counts = vec![0; n];
+        let start = self.cycle_start as u64;
+        let period = self.rounds.len() as u64 - start;
+        // rounds before the cycle, then whole cycles, are counted in bulk
+        let (prefix, repeats) = if rounds <= start { (rounds, 0) } else { (start, (rounds - start) / period) };
+        for monkeys in &self.rounds[..prefix as usize] {
+            for &id in monkeys {
+                counts[id] += 1;
+            }
+        }
+        for monkeys in &self.rounds[self.cycle_start..] {
+            for &id in monkeys {
+                counts[id] += repeats;
+            }
+        }
+        // what's left of the last, partial cycle
+        for r in prefix + repeats * period..rounds {
+            for &id in self.round(r) {
+                counts[id] += 1;
+            }
+        }
+        counts
+    }
+}
+
+// follows every item on its own: without attenuation, an item's fate only depends on which monkey
+// holds it and its worry level modulo supermod, so its trajectory must eventually cycle
+fn track_items(inventories: &[Inventory], monkeys: &[Monkey]) -> Result<Vec<Trajectory>, String> {
+    if !monkeys.iter().all(|m| m.operation.is_modular()) {
+        return Err(String::from("item tracking requires division-free operations"));
+    }
+    let supermod: u64 = monkeys.iter().map(|m| m.divisor).product();
+
+    let mut trajectories = vec![];
+    for (holder, inventory) in inventories.iter().enumerate() {
+        for &item in inventory {
+            let mut state = (holder, item % supermod);
+            let mut seen: HashMap<(usize, u64), usize> = HashMap::new();
+            let mut rounds = vec![];
+            while !seen.contains_key(&state) {
+                seen.insert(state, rounds.len());
+                let (mut id, mut worry) = state;
+                let mut inspected_by = vec![];
+                // monkeys take turns in order, so an item thrown forward is inspected again
+                // within the same round
+                loop {
+                    let monkey = &monkeys[id];
+                    inspected_by.push(id);
+                    worry = compute_worry(&monkey.operation, worry, Some(supermod)).map_err(operation_error(id, monkey))?;
+                    let receiver_id = if worry % monkey.divisor == 0 { monkey.if_branch } else { monkey.else_branch };
+                    if receiver_id <= id {
+                        state = (receiver_id, worry);
+                        break;
+                    }
+                    id = receiver_id;
+                }
+                rounds.push(inspected_by);
+            }
+            trajectories.push(Trajectory { rounds, cycle_start: seen[&state] });
+        }
+    }
+
+    Ok(trajectories)
+}
+
+fn extrapolate_inspections(rounds: u64, trajectories: &[Trajectory], n: usize) -> Vec<u64> {
+    let mut inspections = vec![0; n];
+    for trajectory in trajectories {
+        for (id, count) in trajectory.inspections(rounds, n).into_iter().enumerate() {
+            inspections[id] += count;
+        }
     }
 
     inspections.sort_by(|a, b| b.cmp(a));
@@ -127,13 +495,198 @@ fn count_inspections(rounds: u32, mut inventories: Vec<Inventory>, monkeys: &Vec
     inspections
 }
 
+// inspections per monkey during each of the first `rounds` rounds, unsorted
+fn inspection_history(rounds: u64, trajectories: &[Trajectory], n: usize) -> Vec<Vec<u64>> {
+    (0..rounds).map(|r| {
+        let mut inspections = vec![0; n];
+        for trajectory in trajectories {
+            for &id in trajectory.round(r) {
+                inspections[id] += 1;
+            }
+        }
+        inspections
+    }).collect()
+}
+
 pub fn solve() {
     let input = fs::read_to_string("resources/day11.txt").unwrap();
-    let (inventories, monkeys) = parse(input);
+    let (inventories, monkeys) = parse(&input).unwrap_or_else(|e| panic!("invalid input: {}", e));
+
+    let report = match crate::flag_values("--monkey-report").last() {
+        Some(selection) => parse_report_rounds(selection).unwrap_or_else(|e| panic!("{}", e)),
+        None => vec![],
+    };
+
+    let runs = [("Part 1", 20, Some((|item| item / 3) as fn(u64) -> u64)), ("Part 2", 10000, None)];
+    for (run, rounds, attenuation) in runs {
+        match count_inspections(rounds, inventories.clone(), &monkeys, attenuation, &report, run) {
+            Ok(inspections) => println!("{}", inspections[0] * inspections[1]),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    for rounds in crate::flag_values("--monkey-rounds") {
+        let rounds: u64 = rounds.parse().unwrap_or_else(|_| panic!("invalid round count: {}", rounds));
+        let trajectories = track_items(&inventories, &monkeys).unwrap_or_else(|e| panic!("{}", e));
+        let inspections = extrapolate_inspections(rounds, &trajectories, monkeys.len());
+        println!("after {} rounds: {}", rounds, inspections[0] as u128 * inspections[1] as u128);
+    }
+
+    for rounds in crate::flag_values("--monkey-history") {
+        let rounds: u64 = rounds.parse().unwrap_or_else(|_| panic!("invalid round count: {}", rounds));
+        let trajectories = track_items(&inventories, &monkeys).unwrap_or_else(|e| panic!("{}", e));
+        for (r, inspections) in inspection_history(rounds, &trajectories, monkeys.len()).iter().enumerate() {
+            let counts: Vec<String> = inspections.iter().map(|count| count.to_string()).collect();
+            println!("round {}: {}", r + 1, counts.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_evaluation() {
+        let operation = parse_operation("(old + 3) * old - 10 / 2").unwrap();
+        assert_eq!(compute_worry(&operation, 4, None), Ok(23));
+        assert!(!operation.is_modular());
+        assert_eq!(operation.to_string(), "(old + 3) * old - 10 / 2");
+
+        let operation = parse_operation("old - 5 * (2 + old)").unwrap();
+        assert!(operation.is_modular());
+        assert_eq!(compute_worry(&operation, 100, Some(13)), Ok(6)); // -410 mod 13
+        assert_eq!(operation.to_string(), "old - 5 * (2 + old)");
+        assert_eq!(parse_operation("old - (old - 1)").unwrap().to_string(), "old - (old - 1)");
+
+        assert!(compute_worry(&parse_operation("old * old").unwrap(), u64::MAX, None).is_err());
+        assert!(compute_worry(&parse_operation("old - 2").unwrap(), 1, None).is_err());
+        assert!(compute_worry(&parse_operation("old / (old - old)").unwrap(), 1, None).is_err());
+        assert_eq!(parse_operation("old / 0"), Err(String::from("division by zero")));
+        assert!(parse_operation("old * (old + 1").is_err());
+        assert!(parse_operation("old ^ 2").is_err());
+    }
+
+    #[test]
+    fn test_monkey_parsing() {
+        let input = "Monkey 0:
+  Operation: new = old * 19
+  Starting items: 79, 98
+  Test: divisible by 23
+    If false: throw to monkey 1
+    If true: throw to monkey 1
+
+Monkey 1:
+  Starting items:
+  Operation: new = old + old
+  Test: divisible by 13
+    If true: throw to monkey 0
+    If false: throw to monkey 0
+";
+        let (inventories, monkeys) = parse(input).unwrap();
+        assert_eq!(inventories, vec![vec![79, 98], vec![]]);
+        assert_eq!(monkeys[0].divisor, 23);
+
+        let bad_target = input.replace("If true: throw to monkey 0", "If true: throw to monkey 4");
+        assert_eq!(parse(&bad_target).err().unwrap(), "monkey 1: field 'If true': no monkey 4");
+
+        let bad_id = input.replace("Monkey 1:", "Monkey 2:");
+        assert!(parse(&bad_id).err().unwrap().contains("contiguous"));
+
+        let repeated = input.replace("Starting items: 79, 98", "Starting items: 79, 98\n  Starting items: 1");
+        assert_eq!(parse(&repeated).err().unwrap(), "monkey 0: field 'Starting items': given more than once");
+
+        let empty_item = input.replace("79, 98", "79,,98");
+        assert_eq!(parse(&empty_item).err().unwrap(), "monkey 0: field 'Starting items': invalid item ''");
 
-    let inspections = count_inspections(20, inventories.clone(), &monkeys, Some(|item| item / 3));
-    println!("{}", inspections[0] * inspections[1]);
+        let bad_test = input.replace("divisible by 13", "divisible by thirteen");
+        assert!(parse(&bad_test).err().unwrap().starts_with("monkey 1: field 'Test'"));
+    }
+
+    const EXAMPLE: &str = "Monkey 0:
+  Starting items: 79, 98
+  Operation: new = old * 19
+  Test: divisible by 23
+    If true: throw to monkey 2
+    If false: throw to monkey 3
+
+Monkey 1:
+  Starting items: 54, 65, 75, 74
+  Operation: new = old + 6
+  Test: divisible by 19
+    If true: throw to monkey 2
+    If false: throw to monkey 0
+
+Monkey 2:
+  Starting items: 79, 60, 97
+  Operation: new = old * old
+  Test: divisible by 13
+    If true: throw to monkey 1
+    If false: throw to monkey 3
+
+Monkey 3:
+  Starting items: 74
+  Operation: new = old + 3
+  Test: divisible by 17
+    If true: throw to monkey 0
+    If false: throw to monkey 1
+";
+
+    #[test]
+    fn test_extrapolated_inspections() {
+        let (inventories, monkeys) = parse(EXAMPLE).unwrap();
+        let trajectories = track_items(&inventories, &monkeys).unwrap_or_else(|e| panic!("{}", e));
+        for rounds in [0, 1, 20, 1000, 10000] {
+            let simulated = count_inspections(rounds, inventories.clone(), &monkeys, None, &[], "").unwrap();
+            assert_eq!(extrapolate_inspections(rounds as u64, &trajectories, monkeys.len()), simulated);
+        }
+        let inspections = extrapolate_inspections(10000, &trajectories, monkeys.len());
+        assert_eq!(inspections[0] * inspections[1], 2713310158);
+    }
+
+    #[test]
+    fn test_inspection_history() {
+        let (inventories, monkeys) = parse(EXAMPLE).unwrap();
+        let trajectories = track_items(&inventories, &monkeys).unwrap();
+        let history = inspection_history(20, &trajectories, monkeys.len());
+        assert_eq!(history[0], vec![2, 4, 3, 6]);
 
-    let inspections = count_inspections(10000, inventories.clone(), &monkeys, None);
-    println!("{}", inspections[0] * inspections[1]);
+        let mut totals = vec![0; monkeys.len()];
+        for (r, inspections) in history.iter().enumerate() {
+            for (id, count) in inspections.iter().enumerate() {
+                totals[id] += count;
+            }
+            let mut sorted = totals.clone();
+            sorted.sort_by(|a, b| b.cmp(a));
+            let simulated = count_inspections(r as u32 + 1, inventories.clone(), &monkeys, None, &[], "").unwrap();
+            assert_eq!(sorted, simulated);
+        }
+    }
+
+    #[test]
+    fn test_attenuated_worry_is_exact() {
+        // reducing before dividing by 3 used to give [108, 100, 96, 10] instead of the exact
+        // [109, 100, 96, 12], whose worry levels outgrow u64
+        let (inventories, monkeys) = parse(&EXAMPLE.replace("old * old", "old * old * old")).unwrap();
+        let error = count_inspections(20, inventories, &monkeys, Some(|item| item / 3), &[], "").unwrap_err();
+        assert!(error.starts_with("monkey 2: operation 'new = old * old * old': worry level overflow"), "{}", error);
+    }
+
+    #[test]
+    fn test_division_is_refused_without_attenuation() {
+        let (inventories, monkeys) = parse(&EXAMPLE.replace("old * 19", "old * 19 / 1")).unwrap();
+        assert!(count_inspections(20, inventories.clone(), &monkeys, Some(|item| item / 3), &[], "").is_ok());
+        assert_eq!(count_inspections(10000, inventories.clone(), &monkeys, None, &[], "").err().unwrap(),
+                   "monkey 0: operation 'new = old * 19 / 1': division cannot be combined with reducing worry levels");
+        assert!(track_items(&inventories, &monkeys).is_err());
+    }
+
+    #[test]
+    fn test_report_rounds() {
+        let report = parse_report_rounds("1, 20,every 1000").unwrap();
+        let reported: Vec<u32> = (1..=3000).filter(|&r| is_reported(r, &report)).collect();
+        assert_eq!(reported, vec![1, 20, 1000, 2000, 3000]);
+        assert!(parse_report_rounds("every 0").is_err());
+        assert!(parse_report_rounds("1,,2").is_err());
+    }
 }
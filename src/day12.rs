@@ -1,6 +1,7 @@
 use std::fs;
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
+use std::collections::HashMap;
 use std::collections::HashSet;
 
 struct Grid {
@@ -9,56 +10,67 @@ struct Grid {
     n: usize,
 }
 
-type NeighborFn = fn(&Grid, usize) -> Vec<usize>;
+// which moves between adjacent squares are allowed, and what they cost
+struct ClimbingRule {
+    max_ascent: i32,
+    max_descent: i32,
+    diagonals: bool,
+    // extra cost for each unit of height climbed or descended
+    steepness_cost: i32,
+}
 
-fn downhill_neighbors(grid: &Grid, p: usize) -> Vec<usize> {
-    let m = grid.m;
-    let n = grid.n;
-    let values = &grid.values;
+const PUZZLE_RULE: ClimbingRule = ClimbingRule {
+    max_ascent: 1,
+    max_descent: i32::MAX,
+    diagonals: false,
+    steepness_cost: 0,
+};
 
-    let i = p / n;
-    let j = p - i*n;
+// a reversed search walks the allowed moves backwards, e.g. from the end towards every start
+#[derive(Clone, Copy, PartialEq)]
+enum Direction {
+    Forward,
+    Reversed,
+}
 
-    let mut ns: Vec<usize> = vec![];
-    if i >= 1 && values[(i-1)*n+j] - values[i*n+j] <= 1 {
-        ns.push((i-1)*n+j);
-    }
-    if i + 1 < m && values[(i+1)*n+j] - values[i*n+j] <= 1 {
-        ns.push((i+1)*n+j);
-    }
-    if j >= 1 && values[i*n+j-1] - values[i*n+j] <= 1 {
-        ns.push(i*n+j-1);
-    }
-    if j + 1 < n && values[i*n+j+1] - values[i*n+j] <= 1 {
-        ns.push(i*n+j+1);
+impl ClimbingRule {
+    // moves must keep a positive cost for the search to settle squares in order
+    fn new(max_ascent: i32, max_descent: i32, diagonals: bool, steepness_cost: i32) -> Result<ClimbingRule, String> {
+        if steepness_cost < 0 {
+            return Err(format!("steepness cost must not be negative, got {}", steepness_cost));
+        }
+        Ok(ClimbingRule { max_ascent, max_descent, diagonals, steepness_cost })
     }
 
-    ns
-}
-
-fn uphill_neighbors(grid: &Grid, p: usize) -> Vec<usize> {
-    let m = grid.m;
-    let n = grid.n;
-    let values = &grid.values;
+    fn move_cost(&self, from: i32, to: i32) -> Option<i32> {
+        let climb = to - from;
+        if climb > self.max_ascent || -climb > self.max_descent {
+            None
+        } else {
+            Some(1 + self.steepness_cost * climb.abs())
+        }
+    }
 
-    let i = p / n;
-    let j = p - i*n;
+    fn neighbors(&self, grid: &Grid, p: usize, direction: Direction) -> Vec<(usize, i32)> {
+        let i = (p / grid.n) as i64;
+        let j = (p % grid.n) as i64;
+        let straight = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+        let diagonal = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+        let offsets = if self.diagonals { [straight, diagonal].concat() } else { straight.to_vec() };
 
-    let mut ns: Vec<usize> = vec![];
-    if i >= 1 && values[(i-1)*n+j] - values[i*n+j] >= -1 {
-        ns.push((i-1)*n+j);
+        offsets.iter()
+            .map(|(di, dj)| (i + di, j + dj))
+            .filter(|&(i, j)| i >= 0 && j >= 0 && i < grid.m as i64 && j < grid.n as i64)
+            .map(|(i, j)| i as usize * grid.n + j as usize)
+            .filter_map(|q| {
+                let cost = match direction {
+                    Direction::Forward => self.move_cost(grid.values[p], grid.values[q]),
+                    Direction::Reversed => self.move_cost(grid.values[q], grid.values[p]),
+                };
+                cost.map(|c| (q, c))
+            })
+            .collect()
     }
-    if i + 1 < m && values[(i+1)*n+j] - values[i*n+j] >= -1 {
-        ns.push((i+1)*n+j);
-    }
-    if j >= 1 && values[i*n+j-1] - values[i*n+j] >= -1 {
-        ns.push(i*n+j-1);
-    }
-    if j + 1 < n && values[i*n+j+1] - values[i*n+j] >= -1 {
-        ns.push(i*n+j+1);
-    }
-
-    ns
 }
 
 fn parse(input: &String) -> (usize, usize, Grid) {
@@ -111,58 +123,184 @@ impl PartialOrd for State {
     }
 }
 
+struct Search {
+    // final distance to each frontier node that could be reached
+    distances: HashMap<usize, i32>,
+    // node each position was reached from, to walk routes back to the start
+    previous: Vec<Option<usize>>,
+}
+
+impl Search {
+    // positions from the search start to the given frontier node
+    fn route(&self, target: usize) -> Vec<usize> {
+        let mut route = vec![target];
+        while let Some(p) = self.previous[*route.last().unwrap()] {
+            route.push(p);
+        }
+        route.reverse();
+        route
+    }
+}
+
 // slightly modified Dijkstra
-fn shortest_path(grid: &Grid, start: usize, frontier: HashSet<usize>, neighbors: NeighborFn) -> Vec<i32> {
+fn shortest_path(grid: &Grid, start: usize, frontier: HashSet<usize>, rule: &ClimbingRule, direction: Direction) -> Search {
     let m = grid.m;
     let n = grid.n;
 
     let mut dist: Vec<i32> = vec![i32::MAX; m*n];
+    let mut previous: Vec<Option<usize>> = vec![None; m*n];
 
     let mut heap = BinaryHeap::new();
 
     dist[start] = 0;
     heap.push(State { cost: 0, position: start });
 
-    let mut paths = vec![];
+    let mut distances = HashMap::new();
     while let Some(State { cost, position }) = heap.pop() {
-        if frontier.contains(&position) {
-            paths.push(cost);
-        }
-
-        if frontier.len() == paths.len() {
-            return paths;
-        }
-
+        // stale entry, the position was already settled at a lower cost
         if cost > dist[position] {
             continue;
         }
 
-        for neighbor in neighbors(&grid, position) {
-            let next = State { cost: cost + 1, position: neighbor };
+        if frontier.contains(&position) {
+            distances.insert(position, cost);
+            if distances.len() == frontier.len() {
+                break;
+            }
+        }
+
+        for (neighbor, move_cost) in rule.neighbors(grid, position, direction) {
+            let next = State { cost: cost + move_cost, position: neighbor };
             if next.cost < dist[next.position] {
                 dist[next.position] = next.cost;
+                previous[next.position] = Some(position);
                 heap.push(next);
             }
         }
     }
 
-    paths
+    Search { distances, previous }
+}
+
+// draws the route over the heightmap the way the puzzle does, with an arrow on each square
+// pointing to the next one and 'E' where the route ends
+fn render_route(grid: &Grid, route: &[usize]) -> String {
+    let n = grid.n;
+    let mut canvas: Vec<char> = grid.values.iter()
+        .map(|&v| (b'a' + v as u8) as char)
+        .collect();
+    for step in route.windows(2) {
+        let (from, to) = (step[0], step[1]);
+        let di = (to / n) as i64 - (from / n) as i64;
+        let dj = (to % n) as i64 - (from % n) as i64;
+        canvas[from] = match (di, dj) {
+            (0, 1) => '>',
+            (0, -1) => '<',
+            (-1, 0) => '^',
+            (1, 0) => 'v',
+            (-1, 1) => '↗',
+            (1, 1) => '↘',
+            (1, -1) => '↙',
+            _ => '↖',
+        };
+    }
+    if let Some(&last) = route.last() {
+        canvas[last] = 'E';
+    }
+    canvas.chunks(n)
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 pub fn solve() {
     let input = fs::read_to_string("resources/day12.txt").unwrap();
     let (start, end, grid) = parse(&input);
+    let show_routes = std::env::args().any(|arg| arg == "--route");
+    let rule = ClimbingRule::new(
+        crate::flag_value("--max-ascent", PUZZLE_RULE.max_ascent),
+        crate::flag_value("--max-descent", PUZZLE_RULE.max_descent),
+        std::env::args().any(|arg| arg == "--diagonals"),
+        crate::flag_value("--steepness-cost", PUZZLE_RULE.steepness_cost),
+    ).unwrap_or_else(|e| panic!("{}", e));
     let mut frontier = HashSet::new();
     frontier.insert(end);
-    let shortest_path_from_start = shortest_path(&grid, start, frontier, downhill_neighbors);
-    println!("{}", shortest_path_from_start[0]);
+    let search = shortest_path(&grid, start, frontier, &rule, Direction::Forward);
+    match search.distances.get(&end) {
+        Some(distance) => {
+            println!("{}", distance);
+            if show_routes {
+                println!("{}", render_route(&grid, &search.route(end)));
+            }
+        },
+        None => println!("end unreachable"),
+    }
 
     let starts: HashSet<usize> = grid.values.iter().enumerate()
         .filter(|(_, &c)| c == 0)
         .map(|(i, _)| i)
         .collect();
-                
-    let paths = shortest_path(&grid, end, starts, uphill_neighbors);
-    let best_trail = paths.iter().min();
-    println!("{:?}", best_trail.unwrap());
+
+    // searching from the end, so routes come back reversed
+    let search = shortest_path(&grid, end, starts.clone(), &rule, Direction::Reversed);
+    let (best_start, best_trail) = match search.distances.iter().min_by_key(|(&p, &cost)| (cost, p)) {
+        Some((&p, &cost)) => (p, cost),
+        None => {
+            println!("no start reaches the end");
+            return;
+        },
+    };
+    println!("{:?}", best_trail);
+    let unreachable = starts.iter().filter(|p| !search.distances.contains_key(p)).count();
+    if unreachable > 0 {
+        println!("{} of {} 'a' squares cannot reach the end", unreachable, starts.len());
+    }
+    if show_routes {
+        println!("best start: row {}, column {}", best_start / grid.n, best_start % grid.n);
+        let mut route = search.route(best_start);
+        route.reverse();
+        println!("{}", render_route(&grid, &route));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distances_to_every_target() {
+        let input = String::from("Sabqponm\nabcryxxl\naccszExk\nacctuvwj\nabdefghi\n");
+        let (start, end, grid) = parse(&input);
+
+        let search = shortest_path(&grid, start, HashSet::from([end]), &PUZZLE_RULE, Direction::Forward);
+        assert_eq!(search.distances, HashMap::from([(end, 31)]));
+        assert_eq!(search.route(end).len(), 32);
+
+        let starts: HashSet<usize> = grid.values.iter().enumerate()
+            .filter(|(_, &c)| c == 0)
+            .map(|(i, _)| i)
+            .collect();
+        let search = shortest_path(&grid, end, starts.clone(), &PUZZLE_RULE, Direction::Reversed);
+        assert_eq!(search.distances.len(), starts.len());
+        assert_eq!(search.distances.values().min(), Some(&29));
+        assert_eq!(search.distances[&start], 31);
+    }
+
+    #[test]
+    fn test_reversed_search_matches_forward() {
+        let input = String::from("Sabqponm\nabcryxxl\naccszExk\nacctuvwj\nabdefghi\n");
+        let (start, end, grid) = parse(&input);
+        let rule = ClimbingRule::new(2, 3, true, 4).unwrap();
+        assert!(ClimbingRule::new(1, 1, false, -1).is_err());
+
+        let forward = shortest_path(&grid, start, HashSet::from([end]), &rule, Direction::Forward);
+        let reversed = shortest_path(&grid, end, HashSet::from([start]), &rule, Direction::Reversed);
+        assert_eq!(forward.distances[&end], reversed.distances[&start]);
+
+        let route = forward.route(end);
+        let cost: i32 = route.windows(2)
+            .map(|step| rule.move_cost(grid.values[step[0]], grid.values[step[1]]).unwrap())
+            .sum();
+        assert_eq!(cost, forward.distances[&end]);
+    }
 }
@@ -1,200 +1,492 @@
+use std::fmt;
 use std::fs;
-use std::str::Chars;
+use std::str::CharIndices;
 use std::cmp::Ordering;
 use std::iter::{Iterator, Peekable, zip};
+use std::slice;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+// packets are equal when they compare equal, e.g. [[1]] and [1], as Ord requires; tell them
+// apart by their text
+#[derive(Clone, Debug)]
 enum Packet {
     List(Vec<Packet>),
     Number(u32),
-    Empty,
 }
 
-fn parse_packet_pair(pair: &str) -> (Packet, Packet) {
-    let mut iter = pair.split('\n');
-    let left = iter.next().unwrap();
-    let right = iter.next().unwrap();
-    (parse_packet(left), parse_packet(right))
+// canonical packet text, e.g. [1,[2,3]]
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Packet::Number(n) => write!(f, "{}", n),
+            Packet::List(v) => {
+                write!(f, "[")?;
+                for (i, p) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", p)?;
+                }
+                write!(f, "]")
+            },
+        }
+    }
 }
 
-fn parse_packet(packet: &str) -> Packet {
-    let mut iter = packet.chars().peekable();
-    parse_list(&mut iter)
+fn parse_packet_pair(pair: &str) -> Result<(Packet, Packet), String> {
+    let lines: Vec<&str> = pair.lines().collect();
+    if lines.len() != 2 {
+        return Err(format!("expected 2 packets, found {}", lines.len()));
+    }
+    let left = parse_packet(lines[0]).map_err(|e| format!("left packet, {}", e))?;
+    let right = parse_packet(lines[1]).map_err(|e| format!("right packet, {}", e))?;
+    Ok((left, right))
 }
 
-fn parse_list(mut iter: &mut Peekable<Chars<'_>>) -> Packet {
-    let mut v: Vec<Packet> = vec![];
-    iter.next(); // initial '['
-    
-    while let Some(c) = iter.peek() {
-        match c {
-            ']' => {
-                iter.next();
-                return Packet::List(v);
-            },
-            ',' => { 
-                iter.next();
+// packet  := list
+// list    := '[' (element (',' element)*)? ']'
+// element := list | number
+// errors give the 1-based column where parsing failed
+struct PacketParser<'a> {
+    len: usize,
+    iter: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> PacketParser<'a> {
+    fn error(&mut self, expected: &str) -> String {
+        match self.iter.peek() {
+            Some(&(i, c)) => format!("column {}: expected {}, found '{}'", i + 1, expected, c),
+            None => format!("column {}: expected {}, found end of packet", self.len + 1, expected),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.iter.peek() {
+            Some(&(_, c)) if c == expected => {
+                self.iter.next();
+                Ok(())
             },
-            _   => {
-                match parse_element(&mut iter) {
-                    Packet::Empty => {},
-                    p => { v.push(p); },
-                }
+            _ => Err(self.error(&format!("'{}'", expected))),
+        }
+    }
+
+    fn parse_list(&mut self) -> Result<Vec<Packet>, String> {
+        self.expect('[')?;
+        let mut v: Vec<Packet> = vec![];
+        if let Some((_, ']')) = self.iter.peek() {
+            self.iter.next();
+            return Ok(v);
+        }
+        loop {
+            v.push(self.parse_element()?);
+            match self.iter.peek() {
+                Some((_, ',')) => { self.iter.next(); },
+                Some((_, ']')) => {
+                    self.iter.next();
+                    return Ok(v);
+                },
+                _ => return Err(self.error("',' or ']'")),
             }
         }
     }
 
-    panic!("unterminated list");
+    fn parse_element(&mut self) -> Result<Packet, String> {
+        match self.iter.peek() {
+            Some((_, '[')) => Ok(Packet::List(self.parse_list()?)),
+            Some((_, '0'..='9')) => self.parse_num(),
+            _ => Err(self.error("'[' or a number")),
+        }
+    }
+
+    fn parse_num(&mut self) -> Result<Packet, String> {
+        let start = self.iter.peek().map(|&(i, _)| i + 1).unwrap_or(self.len + 1);
+        let mut n: u32 = 0;
+        while let Some(&(_, c @ '0'..='9')) = self.iter.peek() {
+            n = n.checked_mul(10)
+                .and_then(|n| n.checked_add(c.to_digit(10).unwrap()))
+                .ok_or(format!("column {}: number too large", start))?;
+            self.iter.next();
+        }
+        Ok(Packet::Number(n))
+    }
 }
 
-fn parse_element(iter: &mut Peekable<Chars<'_>>) -> Packet {
-    match iter.peek() {
-        Some(c) => match c {
-            '[' => parse_list(iter),
-            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => parse_num(iter),
-            _ => panic!("unexpected elem: {}", c),
-        },
-        _ => Packet::Empty,
+fn parse_packet(packet: &str) -> Result<Packet, String> {
+    let mut parser = PacketParser { len: packet.chars().count(), iter: packet.char_indices().peekable() };
+    let list = parser.parse_list()?;
+    if parser.iter.peek().is_some() {
+        return Err(parser.error("end of packet"));
     }
+    Ok(Packet::List(list))
 }
 
-fn parse_num(iter: &mut Peekable<Chars<'_>>) -> Packet {
-    let mut s: String = String::new();
-    while let Some(c) = iter.peek() {
-        match c {
-            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
-                s.push(*c);
-                iter.next();
-            },
-            _ => {
-                return Packet::Number(u32::from_str_radix(&s, 10).unwrap());
-            },
+fn compare_lists(left: &[Packet], right: &[Packet]) -> Ordering {
+    zip(left.iter(), right.iter())
+        .map(|(l, r)| l.cmp(r))
+        .find(|&o| o != Ordering::Equal)
+        .unwrap_or_else(|| left.len().cmp(&right.len()))
+}
+
+// a number compared to a list is promoted to a singleton list: borrowing it as a one element slice
+// does that without allocating
+impl Ord for Packet {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Packet::Number(nl), Packet::Number(nr)) => nl.cmp(nr),
+            (Packet::List(vl), Packet::List(vr)) => compare_lists(vl, vr),
+            (Packet::Number(_), Packet::List(vr)) => compare_lists(slice::from_ref(self), vr),
+            (Packet::List(vl), Packet::Number(_)) => compare_lists(vl, slice::from_ref(other)),
         }
     }
-    panic!("unterminated input");
 }
 
-fn fix_packet_pair(left: &Packet, right: &Packet) -> (Packet, Packet) {
-    (fix_packet(&left, &right), fix_packet(&right, &left))
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Number(u32),
 }
 
-fn fix_packet(broken: &Packet, reference: &Packet) -> Packet {
-    match broken {
-        Packet::Number(n) => fix_number(*n, &reference),
-        Packet::List(v) => fix_list(v, &reference),
-        _ => panic!("unexpected packet"),
-    }
+// what the packet grammar allows next, with the same wording as PacketParser's errors
+#[derive(Clone, Copy, PartialEq)]
+enum Expected {
+    Packet,
+    ElementOrClose,
+    Element,
+    SeparatorOrClose,
+    End,
 }
 
-fn fix_list(v: &Vec<Packet>, reference: &Packet) -> Packet {
-    match reference {
-        Packet::Number(_) => Packet::List(v.to_vec()),
-        Packet::List(v2) => fix_each(v, v2),
-        _ => panic!("unexpected packet"),
+impl Expected {
+    fn describe(&self) -> &'static str {
+        match self {
+            Expected::Packet => "'['",
+            Expected::ElementOrClose | Expected::Element => "'[' or a number",
+            Expected::SeparatorOrClose => "',' or ']'",
+            Expected::End => "end of packet",
+        }
     }
 }
-fn fix_each(broken: &Vec<Packet>, reference: &Vec<Packet>) -> Packet {
-    let mut fixed: Vec<_> = zip(broken.iter(), reference.iter())
-        .map(|(b, f)| fix_packet(b, f))
-        .collect();
-    broken.iter().skip(fixed.len()).for_each(|e| fixed.push(e.clone()));
-    Packet::List(fixed)
+
+// Tokens of a packet's text, checked against the same grammar as parse_packet. A number that has
+// to be compared to a list is wrapped on the fly: it's replayed after the '[' it stands in for,
+// followed by as many ']' as it was wrapped.
+struct Tokens<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+    expected: Expected,
+    wrapped: Option<u32>,
+    closes: usize,
 }
 
-fn fix_number(n: u32, reference: &Packet) -> Packet {
-    match reference {
-        Packet::Number(_) => Packet::Number(n),
-        Packet::List(v) => Packet::List(vec![fix_number_vec(n, &v)]),
-        _ => panic!("unexpected packet"),
+impl<'a> Tokens<'a> {
+    fn new(packet: &'a str) -> Tokens<'a> {
+        Tokens { text: packet, pos: 0, depth: 0, expected: Expected::Packet, wrapped: None, closes: 0 }
     }
-}
 
-fn fix_number_vec(n: u32, reference: &Vec<Packet>) -> Packet {
-    if reference.is_empty() {
-        Packet::Number(n)
-    } else {
-        fix_number(n, &reference[0])
+    fn wrap(&mut self, n: u32) {
+        self.wrapped = Some(n);
+        self.closes += 1;
     }
-}
 
-impl Ord for Packet {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match self {
-            Packet::Number(nl) => {
-                match other {
-                    Packet::Number(nr) => nl.cmp(nr),
-                    Packet::List(_) => {
-                        let (left, right) = fix_packet_pair(self, other);
-                        left.cmp(&right)
-                    },
-                    t => panic!("unexpected token: {:?}", t),
-                }
+    fn error(&self) -> String {
+        match self.text[self.pos..].chars().next() {
+            Some(c) => format!("column {}: expected {}, found '{}'", self.pos + 1, self.expected.describe(), c),
+            None => format!("column {}: expected {}, found end of packet", self.pos + 1, self.expected.describe()),
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<Token>, String> {
+        if let Some(n) = self.wrapped.take() {
+            return Ok(Some(Token::Number(n)));
+        }
+        if self.closes > 0 {
+            self.closes -= 1;
+            return Ok(Some(Token::Close));
+        }
+        let bytes = self.text.as_bytes();
+        if self.expected == Expected::SeparatorOrClose && bytes.get(self.pos) == Some(&b',') {
+            self.pos += 1;
+            self.expected = Expected::Element;
+        }
+        let token = match (self.expected, bytes.get(self.pos)) {
+            (Expected::End, None) => return Ok(None),
+            (Expected::Packet | Expected::ElementOrClose | Expected::Element, Some(b'[')) => {
+                self.pos += 1;
+                self.depth += 1;
+                self.expected = Expected::ElementOrClose;
+                Token::Open
             },
-            Packet::List(vl) => {
-                match other {
-                    Packet::List(vr) => {
-                        let elem_order = zip(vl.iter(), vr.iter())
-                            .map(|(el, er)| el.cmp(er))
-                            .skip_while(|&o| o == Ordering::Equal)
-                            .nth(0)
-                            .unwrap_or(Ordering::Equal);
-                        match elem_order {
-                            Ordering::Equal => vl.len().cmp(&vr.len()),
-                            o => o,
-                        }
-                    },
-                    Packet::Number(_) => {
-                        let (left, right) = fix_packet_pair(self, other);
-                        left.cmp(&right)
-                    },
-                    t => panic!("unexpected token: {:?}", t),
+            (Expected::ElementOrClose | Expected::SeparatorOrClose, Some(b']')) => {
+                self.pos += 1;
+                self.depth -= 1;
+                self.expected = if self.depth == 0 { Expected::End } else { Expected::SeparatorOrClose };
+                Token::Close
+            },
+            (Expected::ElementOrClose | Expected::Element, Some(b'0'..=b'9')) => {
+                let start = self.pos + 1;
+                let mut n: u32 = 0;
+                while let Some(d @ b'0'..=b'9') = bytes.get(self.pos) {
+                    n = n.checked_mul(10)
+                        .and_then(|n| n.checked_add((d - b'0') as u32))
+                        .ok_or(format!("column {}: number too large", start))?;
+                    self.pos += 1;
                 }
+                self.expected = Expected::SeparatorOrClose;
+                Token::Number(n)
             },
-            t => panic!("unexpected token: {:?}", t),
+            _ => return Err(self.error()),
+        };
+        Ok(Some(token))
+    }
+
+    // reads what's left, so that the whole packet is checked
+    fn finish(&mut self) -> Result<(), String> {
+        while self.next()?.is_some() {}
+        Ok(())
+    }
+}
+
+// compares two packets straight from their text, without building them
+fn compare_packets(left: &str, right: &str) -> Result<Ordering, String> {
+    let mut left = Tokens::new(left);
+    let mut right = Tokens::new(right);
+    let left_error = |e| format!("left packet, {}", e);
+    let right_error = |e| format!("right packet, {}", e);
+    let order = loop {
+        match (left.next().map_err(left_error)?, right.next().map_err(right_error)?) {
+            (None, None) => break Ordering::Equal,
+            (Some(Token::Number(nl)), Some(Token::Number(nr))) if nl != nr => break nl.cmp(&nr),
+            (Some(Token::Close), Some(Token::Close)) => {},
+            (Some(Token::Close), _) | (None, _) => break Ordering::Less,
+            (_, Some(Token::Close)) | (_, None) => break Ordering::Greater,
+            (Some(Token::Number(nl)), Some(Token::Open)) => left.wrap(nl),
+            (Some(Token::Open), Some(Token::Number(nr))) => right.wrap(nr),
+            _ => {},
         }
+    };
+    left.finish().map_err(left_error)?;
+    right.finish().map_err(right_error)?;
+    Ok(order)
+}
+
+impl PartialEq for Packet {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
+impl Eq for Packet {}
+
 impl PartialOrd for Packet {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-fn is_ordered(pair: &(Packet, Packet)) -> bool {
-    match pair.0.cmp(&pair.1) {
-        Ordering::Less | Ordering::Equal => true,
-        Ordering::Greater => false,
-    }
-}
-
 pub fn solve() {
     let input = fs::read_to_string("resources/day13.txt").unwrap();
     let ordered_pairs: Vec<_> = input.split("\n\n")
-        .map(parse_packet_pair)
+        .enumerate()
+        .map(|(i, pair)| parse_packet_pair(pair).unwrap_or_else(|e| panic!("pair {}: {}", i + 1, e)))
         .collect();
 
-    let ordered_index_sum: usize = ordered_pairs.iter()
+    let ordered_index_sum: usize = input.split("\n\n")
+        .map(|pair| pair.lines().collect::<Vec<_>>())
         .enumerate()
-        .filter(|(_, pair)| is_ordered(&pair))
+        .filter(|(i, pair)| {
+            let order = compare_packets(pair[0], pair[1]).unwrap_or_else(|e| panic!("pair {}: {}", i + 1, e));
+            order != Ordering::Greater
+        })
         .map(|(i, _)| i+1)
         .sum();
 
     println!("{}", ordered_index_sum);
 
-    let mut packets: Vec<_> = ordered_pairs.into_iter()
+    let packets: Vec<_> = ordered_pairs.into_iter()
         .flat_map(|pair| [pair.0, pair.1].into_iter())
         .collect();
 
-    let dp1 = Packet::List(vec![ Packet::List(vec![ Packet::Number(2) ])]);
-    let dp2 = Packet::List(vec![ Packet::List(vec![ Packet::Number(6) ])]);
-    packets.push(dp1.clone());
-    packets.push(dp2.clone());
+    let divider_args = crate::flag_values("--divider");
+    let dividers: Vec<Packet> = if divider_args.is_empty() {
+        vec![
+            Packet::List(vec![ Packet::List(vec![ Packet::Number(2) ])]),
+            Packet::List(vec![ Packet::List(vec![ Packet::Number(6) ])]),
+        ]
+    } else {
+        divider_args.iter()
+            .map(|text| parse_packet(text).unwrap_or_else(|e| panic!("divider {}: {}", text, e)))
+            .collect()
+    };
+
+    let positions = divider_positions(&packets, &dividers);
+    if !divider_args.is_empty() {
+        for (divider, position) in zip(&dividers, &positions) {
+            println!("{}: {}", divider, position);
+        }
+    }
+    println!("{}", positions.iter().product::<usize>());
+}
+
+// 1-based position of each divider once added to the packets and sorted, found by counting what
+// comes before it instead of sorting. Dividers go before packets equal to them, and equal dividers
+// keep their order.
+fn divider_positions(packets: &[Packet], dividers: &[Packet]) -> Vec<usize> {
+    let mut positions: Vec<usize> = dividers.iter().enumerate()
+        .map(|(i, d)| 1 + dividers.iter().enumerate()
+             .filter(|&(j, other)| match other.cmp(d) {
+                 Ordering::Less => true,
+                 Ordering::Equal => j < i,
+                 Ordering::Greater => false,
+             })
+             .count())
+        .collect();
+
+    for packet in packets {
+        for (position, divider) in positions.iter_mut().zip(dividers) {
+            if packet < divider {
+                *position += 1;
+            }
+        }
+    }
+
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift64, good enough to generate test packets
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    fn random_list(rng: &mut Rng, depth: u32) -> Packet {
+        let len = rng.below(5);
+        Packet::List((0..len).map(|_| random_element(rng, depth + 1)).collect())
+    }
+
+    fn random_element(rng: &mut Rng, depth: u32) -> Packet {
+        // small numbers and shallow lists, so that packets often share prefixes
+        if depth < 4 && rng.below(3) == 0 {
+            random_list(rng, depth)
+        } else {
+            Packet::Number(rng.below(11) as u32)
+        }
+    }
+
+    fn random_packets(seed: u64, count: usize) -> Vec<Packet> {
+        let mut rng = Rng(seed);
+        (0..count).map(|_| random_list(&mut rng, 0)).collect()
+    }
+
+    #[test]
+    fn test_display() {
+        let packet = parse_packet("[1,[2,[3,[4,[5,6,7]]]],8,9]").unwrap();
+        assert_eq!(packet.to_string(), "[1,[2,[3,[4,[5,6,7]]]],8,9]");
+        assert_eq!(parse_packet("[[],[[]],10]").unwrap().to_string(), "[[],[[]],10]");
+    }
+
+    #[test]
+    fn test_parse_print_round_trip() {
+        for packet in random_packets(0x5eed, 2000) {
+            let text = packet.to_string();
+            assert_eq!(parse_packet(&text).map(|p| p.to_string()), Ok(text));
+        }
+    }
+
+    #[test]
+    fn test_total_order() {
+        let packets = random_packets(0xdecaf, 120);
+        for a in &packets {
+            assert_eq!(a.cmp(a), Ordering::Equal, "{}", a);
+            for b in &packets {
+                let ab = a.cmp(b);
+                assert_eq!(ab, b.cmp(a).reverse(), "{} {}", a, b);
+                assert_eq!(ab == Ordering::Equal, a == b, "{} {}", a, b);
+                for c in &packets {
+                    if ab != Ordering::Greater && b.cmp(c) != Ordering::Greater {
+                        assert_ne!(a.cmp(c), Ordering::Greater, "{} {} {}", a, b, c);
+                    }
+                }
+            }
+        }
+    }
 
-    packets.sort();
+    #[test]
+    fn test_equality_follows_order() {
+        let nested = parse_packet("[[1]]").unwrap();
+        let flat = parse_packet("[1]").unwrap();
+        assert_eq!(nested.cmp(&flat), Ordering::Equal);
+        assert_eq!(nested, flat);
+        assert_ne!(nested.to_string(), flat.to_string());
+    }
 
-    let dp1_idx = packets.iter().position(|p| *p == dp1).unwrap() + 1;
-    let dp2_idx = packets.iter().position(|p| *p == dp2).unwrap() + 1;
+    #[test]
+    fn test_streaming_comparison() {
+        assert_eq!(compare_packets("[[1],[2,3,4]]", "[[1],4]"), Ok(Ordering::Less));
+        assert_eq!(compare_packets("[9]", "[[8,7,6]]"), Ok(Ordering::Greater));
+        assert_eq!(compare_packets("[[[]]]", "[[]]"), Ok(Ordering::Greater));
+        assert_eq!(compare_packets("[3]", "[[[[3]]]]"), Ok(Ordering::Equal));
+        assert_eq!(compare_packets("[10,[]]", "[[10]]"), Ok(Ordering::Greater));
 
-    println!("{}", dp1_idx * dp2_idx);
+        let packets = random_packets(0xfeed, 150);
+        for a in &packets {
+            for b in &packets {
+                assert_eq!(compare_packets(&a.to_string(), &b.to_string()), Ok(a.cmp(b)), "{} {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_strict_grammar() {
+        // compare_packets reports the same errors, even past the point where the order is known
+        let error = |packet| {
+            let message = parse_packet(packet).unwrap_err();
+            assert_eq!(compare_packets(packet, "[0]"), Err(format!("left packet, {}", message)));
+            assert_eq!(compare_packets("[0]", packet), Err(format!("right packet, {}", message)));
+            message
+        };
+        assert_eq!(error("[1,,2]"), "column 4: expected '[' or a number, found ','");
+        assert_eq!(error("[1,2"), "column 5: expected ',' or ']', found end of packet");
+        assert_eq!(error("[1,2,]"), "column 6: expected '[' or a number, found ']'");
+        assert_eq!(error("[1][2]"), "column 4: expected end of packet, found '['");
+        assert_eq!(error("5"), "column 1: expected '[', found '5'");
+        assert_eq!(error(""), "column 1: expected '[', found end of packet");
+        assert_eq!(error("[1 2]"), "column 3: expected ',' or ']', found ' '");
+        assert_eq!(error("[[99999999999]]"), "column 3: number too large");
+        assert_eq!(parse_packet_pair("[1]\n[2]\n[3]").unwrap_err(), "expected 2 packets, found 3");
+        assert_eq!(parse_packet_pair("[1]\n[2,]").unwrap_err(), "right packet, column 4: expected '[' or a number, found ']'");
+    }
+
+    #[test]
+    fn test_divider_positions() {
+        let packets = random_packets(0xd1d, 300);
+        let dividers: Vec<Packet> = ["[[2]]", "[[6]]", "[]", "[[2]]", "[5,[3]]"].iter()
+            .map(|text| parse_packet(text).unwrap())
+            .collect();
+        let positions = divider_positions(&packets, &dividers);
+
+        // tag dividers so they can be told apart from equal packets once sorted
+        let mut sorted: Vec<(&Packet, Option<usize>)> = packets.iter().map(|p| (p, None))
+            .chain(dividers.iter().enumerate().map(|(i, d)| (d, Some(i))))
+            .collect();
+        sorted.sort_by(|(a, ta), (b, tb)| a.cmp(b).then_with(|| match (ta, tb) {
+            (Some(i), Some(j)) => i.cmp(j),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }));
+        for (i, &position) in positions.iter().enumerate() {
+            assert_eq!(sorted[position - 1].1, Some(i));
+        }
+    }
 }
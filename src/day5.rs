@@ -1,97 +1,343 @@
+use std::fmt;
 use std::fs;
-use std::str::FromStr;
 
 #[derive(Debug)]
 struct Instruction {
-    amount: u8,
+    amount: usize,
     from: usize,
     to: usize,
+    // 1-based line in the input, for error messages
+    line: usize,
 }
 
-type Stacks = Vec<Vec<char>>;
-
-fn parse_input(input: String) -> (Stacks, Vec<Instruction>) {
-    let mut stacks: Stacks = vec![];    
-    let mut nstacks: usize = 0;
-    let mut rinstructions = vec![];
-    for line in input.lines().rev() {
-        // crate
-        let mut words = line.split_whitespace();
-        let first = words.next(); 
-        match first {
-            Some("move") => { 
-                let amount = u8::from_str(words.next().unwrap()).unwrap();
-                words.next(); // 'from' keyword
-                let from = usize::from_str(words.next().unwrap()).unwrap();
-                words.next(); // 'to' keyword
-                let to = usize::from_str(words.next().unwrap()).unwrap();
-
-                rinstructions.push(Instruction { amount: amount, from: from, to: to });
-            }
-            Some("1") => {
-                nstacks = usize::from_str(words.last().unwrap()).unwrap();
-                for _ in 0..nstacks { stacks.push(vec![]); }
-            }
-            // empty separator line
-            None => {}
-            // crate row
-            _ => {
-                let mut iter = line.chars();
-                for istack in 0..nstacks {
-                    iter.next(); // '[' or empty space
-                    match iter.next() {
-                        Some(' ') => {},
-                        Some(c) => { stacks[istack].push(c); },
-                        _ => { }
-                    }
-                    iter.next(); // ']' or empty space
-                    iter.next(); // ' ' separator or None
-                }
+// crate ids can be longer than a single letter
+type Crate = String;
+type Stacks = Vec<Vec<Crate>>;
+
+// whitespace separated words of a line, with the column where each starts and ends
+fn words_with_columns(line: &str) -> Vec<(usize, usize, &str)> {
+    let mut words = vec![];
+    let mut start: Option<(usize, usize)> = None;
+    for (column, (offset, c)) in line.char_indices().enumerate().chain([(line.chars().count(), (line.len(), ' '))]) {
+        match (start, c.is_whitespace()) {
+            (None, false) => { start = Some((column, offset)); },
+            (Some((first, from)), true) => {
+                words.push((first, column - 1, &line[from..offset]));
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    words
+}
+
+// The drawing's last line numbers the stacks; crates sit in brackets above their stack's number,
+// possibly spilling over on either side when ids are wider than numbers.
+fn parse_drawing(lines: &[&str]) -> Result<Stacks, String> {
+    let (number_row, crate_rows) = lines.split_last().ok_or("missing stack drawing")?;
+    let labels = words_with_columns(number_row);
+    for (i, (_, _, label)) in labels.iter().enumerate() {
+        if label.parse() != Ok(i + 1) {
+            return Err(format!("line {}: expected stack {}, found '{}'", crate_rows.len() + 1, i + 1, label));
+        }
+    }
+
+    let mut stacks: Stacks = vec![vec![]; labels.len()];
+    for (i, line) in crate_rows.iter().enumerate().rev() {
+        for (first, last, word) in words_with_columns(line) {
+            let id = word.strip_prefix('[').and_then(|w| w.strip_suffix(']'))
+                .filter(|id| !id.is_empty() && !id.contains(['[', ']']))
+                .ok_or(format!("line {}: malformed crate '{}' at column {}", i + 1, word, first + 1))?;
+            // the overlapping stack number closest to the crate's center
+            let stack = labels.iter()
+                .enumerate()
+                .filter(|(_, &(lfirst, llast, _))| lfirst <= last && first <= llast)
+                .min_by_key(|(_, &(lfirst, llast, _))| (lfirst + llast).abs_diff(first + last))
+                .map(|(s, _)| s)
+                .ok_or(format!("line {}: crate '{}' at column {} is not above any stack", i + 1, word, first + 1))?;
+            if stacks[stack].len() != crate_rows.len() - 1 - i {
+                return Err(format!("line {}: crate '{}' is floating above stack {}", i + 1, word, stack + 1));
             }
+            stacks[stack].push(String::from(id));
         }
     }
 
-    (stacks, rinstructions)
+    Ok(stacks)
+}
+
+fn parse_instruction(line: &str, number: usize) -> Result<Instruction, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[..] {
+        ["move", amount, "from", from, "to", to] => {
+            let parse = |n: &str| n.parse().map_err(|_| format!("line {}: invalid number '{}'", number, n));
+            Ok(Instruction { amount: parse(amount)?, from: parse(from)?, to: parse(to)?, line: number })
+        },
+        _ => Err(format!("line {}: malformed instruction '{}'", number, line)),
+    }
+}
+
+// the stack drawing, then a blank line, then the instructions
+fn parse_input(input: String) -> Result<(Stacks, Vec<Instruction>), String> {
+    let lines: Vec<&str> = input.lines().collect();
+    let separator = lines.iter().position(|line| line.trim().is_empty()).unwrap_or(lines.len());
+    let stacks = parse_drawing(&lines[..separator])?;
+    let instructions = lines.iter()
+        .enumerate()
+        .skip(separator + 1)
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| parse_instruction(line, i + 1))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((stacks, instructions))
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "move {} from {} to {}", self.amount, self.from, self.to)
+    }
+}
+
+// crane models: how many crates a single lift can carry. Crates lifted together keep their order,
+// so a crane lifting one crate at a time reverses the moved crates.
+#[derive(Clone, Copy, Debug)]
+enum Crane {
+    CrateMover9000,
+    CrateMover9001,
+    Limited(usize),
 }
 
-fn apply_instructions<F>(mut stacks: Stacks, rinstructions: &Vec<Instruction>, buffer_factory: F) -> Stacks 
-    where F: Fn(Vec<char>) -> Vec<char> {
-    for instruction in rinstructions.iter().rev() {
-        let from = &mut stacks[instruction.from - 1];
-        let mut buffer: Vec<char> = vec![]; 
-        for _ in  0..instruction.amount {
-            match from.pop() {
-                Some(c) => { buffer.push(c); }
-                _ => {}
+impl Crane {
+    fn from_name(name: &str) -> Result<Crane, String> {
+        match name {
+            "9000" => Ok(Crane::CrateMover9000),
+            "9001" => Ok(Crane::CrateMover9001),
+            _ => name.strip_prefix("limit=")
+                .and_then(|k| k.parse().ok())
+                .filter(|&k| k > 0)
+                .map(Crane::Limited)
+                .ok_or(format!("unknown crane: {} (expected 9000, 9001 or limit=K)", name)),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Crane::CrateMover9000 => String::from("CrateMover 9000"),
+            Crane::CrateMover9001 => String::from("CrateMover 9001"),
+            Crane::Limited(k) => format!("crane lifting up to {} crates", k),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            Crane::CrateMover9000 => 1,
+            Crane::CrateMover9001 => usize::MAX,
+            Crane::Limited(k) => *k,
+        }
+    }
+
+    fn move_crates(&self, stacks: &mut Stacks, instruction: &Instruction) {
+        let mut remaining = instruction.amount;
+        while remaining > 0 {
+            let from = &mut stacks[instruction.from - 1];
+            let lift = remaining.min(self.capacity()).min(from.len());
+            if lift == 0 {
+                break;
             }
+            let buffer = from.split_off(from.len() - lift);
+            stacks[instruction.to - 1].extend(buffer);
+            remaining -= lift;
         }
+    }
+}
+
+// same layout as the drawing at the top of the input; all slots are as wide as the widest crate
+// or stack number
+fn draw_stacks(stacks: &Stacks) -> String {
+    let height = stacks.iter().map(|stack| stack.len()).max().unwrap_or(0);
+    let width = stacks.iter().flatten().map(|id| id.chars().count() + 2)
+        .chain([stacks.len().to_string().len() + 2])
+        .max()
+        .unwrap();
+    let mut lines: Vec<String> = (0..height).rev()
+        .map(|level| stacks.iter()
+             .map(|stack| match stack.get(level) {
+                 Some(id) => format!("{:^width$}", format!("[{}]", id)),
+                 None => " ".repeat(width),
+             })
+             .collect::<Vec<_>>()
+             .join(" ")
+             .trim_end()
+             .to_string())
+        .collect();
+    lines.push((1..=stacks.len()).map(|i| format!("{:^width$}", i)).collect::<Vec<_>>().join(" "));
+    lines.join("\n")
+}
 
-        let to = &mut stacks[instruction.to - 1];
-        for c in buffer_factory(buffer) {
-            to.push(c);
+// what to do with moves of more crates than the stack holds
+#[derive(Clone, Copy, PartialEq)]
+enum Validation {
+    Strict,
+    // move whatever is there, as the original solution did
+    Lenient,
+}
+
+fn validate(stacks: &Stacks, instruction: &Instruction, validation: Validation) -> Result<(), String> {
+    let error = |message: String| Err(format!("line {}: {}: {}", instruction.line, instruction, message));
+    for index in [instruction.from, instruction.to] {
+        if index == 0 || index > stacks.len() {
+            return error(format!("no stack {}, stacks are numbered 1 to {}", index, stacks.len()));
         }
     }
+    let available = stacks[instruction.from - 1].len();
+    if validation == Validation::Strict && instruction.amount > available {
+        return error(format!("stack {} only holds {} crates", instruction.from, available));
+    }
+    Ok(())
+}
 
-    stacks
+fn apply_instructions(mut stacks: Stacks, instructions: &[Instruction], crane: &Crane,
+                      validation: Validation, trace: bool) -> Result<Stacks, String> {
+    // each crane's run is traced in turn
+    if trace {
+        println!("== {} ==\n", crane.name());
+    }
+    for instruction in instructions {
+        validate(&stacks, instruction, validation)?;
+        crane.move_crates(&mut stacks, instruction);
+        if trace {
+            println!("{}\n\n{}\n", instruction, draw_stacks(&stacks));
+        }
+    }
+
+    Ok(stacks)
 }
 
-fn crates_at_top_of_stacks(stacks: &Stacks) -> Vec<char> {
-    let mut crates: Vec<char> = vec![];
-    for stack in stacks {
-        match stack.last() {
-            Some(c) => { crates.push(*c); }
-            _ => {}
+// the puzzle's answer: the crate on top of each stack, read left to right, with a placeholder
+// for empty stacks so that the others stay in place
+#[derive(Debug, PartialEq)]
+struct TopCrates(Vec<Option<Crate>>);
+
+const EMPTY_STACK: &str = "_";
+
+// single letter ids are run together, e.g. CMZ; anything else could be read more than one way, so
+// each id is bracketed as in the drawing, with [] for an empty stack
+impl fmt::Display for TopCrates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let compact = self.0.iter().flatten().all(|id| id.chars().count() == 1 && id != EMPTY_STACK);
+        for (i, top) in self.0.iter().enumerate() {
+            if compact {
+                write!(f, "{}", top.as_deref().unwrap_or(EMPTY_STACK))?;
+            } else {
+                let separator = if i > 0 { " " } else { "" };
+                write!(f, "{}[{}]", separator, top.as_deref().unwrap_or(""))?;
+            }
         }
+        Ok(())
     }
+}
 
-    crates
+fn crates_at_top_of_stacks(stacks: &Stacks) -> TopCrates {
+    TopCrates(stacks.iter().map(|stack| stack.last().cloned()).collect())
 }
 
 pub fn solve() {
     let input = fs::read_to_string("resources/day5.txt").unwrap();
-    let (stacks, rinstructions) = parse_input(input);
-    let stacks_9000 = apply_instructions(stacks.clone(), &rinstructions, |buffer| buffer.iter().map(|c| *c).collect());
-    println!("{:?}", crates_at_top_of_stacks(&stacks_9000));
-    let stacks_9001 = apply_instructions(stacks.clone(), &rinstructions, |buffer| buffer.iter().rev().map(|c| *c).collect());
-    println!("{:?}", crates_at_top_of_stacks(&stacks_9001));
+    let (stacks, instructions) = parse_input(input).unwrap_or_else(|e| panic!("invalid input: {}", e));
+    let trace = std::env::args().any(|arg| arg == "--crane-trace");
+    let validation = if std::env::args().any(|arg| arg == "--lenient") { Validation::Lenient } else { Validation::Strict };
+    let run = |crane: &Crane| apply_instructions(stacks.clone(), &instructions, crane, validation, trace)
+        .unwrap_or_else(|e| panic!("invalid instruction: {}", e));
+
+    let stacks_9000 = run(&Crane::CrateMover9000);
+    println!("{}", crates_at_top_of_stacks(&stacks_9000));
+    let stacks_9001 = run(&Crane::CrateMover9001);
+    println!("{}", crates_at_top_of_stacks(&stacks_9001));
+
+    for name in crate::flag_values("--crane") {
+        let crane = Crane::from_name(&name).unwrap_or_else(|e| panic!("{}", e));
+        let moved = run(&crane);
+        println!("{}: {}", crane.name(), crates_at_top_of_stacks(&moved));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "    [D]    
+[N] [C]    
+[Z] [M] [P]
+ 1   2   3 
+
+move 1 from 2 to 1
+move 3 from 1 to 3
+move 2 from 2 to 1
+move 1 from 1 to 2
+";
+
+    #[test]
+    fn test_cranes() {
+        let (stacks, instructions) = parse_input(String::from(EXAMPLE)).unwrap();
+        assert_eq!(draw_stacks(&stacks), "    [D]\n[N] [C]\n[Z] [M] [P]\n 1   2   3 ");
+
+        let moved = apply_instructions(stacks.clone(), &instructions, &Crane::CrateMover9000, Validation::Strict, false).unwrap();
+        assert_eq!(crates_at_top_of_stacks(&moved).to_string(), "CMZ");
+        let moved = apply_instructions(stacks.clone(), &instructions, &Crane::CrateMover9001, Validation::Strict, false).unwrap();
+        assert_eq!(crates_at_top_of_stacks(&moved).to_string(), "MCD");
+        let moved = apply_instructions(stacks.clone(), &instructions, &Crane::Limited(2), Validation::Strict, false).unwrap();
+        assert_eq!(draw_stacks(&moved), "        [Z]\n        [D]\n        [N]\n[M] [C] [P]\n 1   2   3 ");
+    }
+
+    #[test]
+    fn test_invalid_instructions() {
+        let (stacks, instructions) = parse_input(EXAMPLE.replace("move 3 from 1 to 3", "move 4 from 1 to 3")).unwrap();
+        let result = apply_instructions(stacks.clone(), &instructions, &Crane::CrateMover9000, Validation::Strict, false);
+        assert_eq!(result.unwrap_err(), "line 7: move 4 from 1 to 3: stack 1 only holds 3 crates");
+        let moved = apply_instructions(stacks, &instructions, &Crane::CrateMover9000, Validation::Lenient, false).unwrap();
+        assert_eq!(crates_at_top_of_stacks(&moved).to_string(), "CMZ");
+
+        for bad in ["move 1 from 4 to 1", "move 1 from 0 to 1", "move 1 from 1 to 9"] {
+            let (stacks, instructions) = parse_input(EXAMPLE.replace("move 1 from 1 to 2", bad)).unwrap();
+            let result = apply_instructions(stacks, &instructions, &Crane::CrateMover9000, Validation::Lenient, false);
+            assert!(result.unwrap_err().starts_with(&format!("line 9: {}: no stack", bad)));
+        }
+
+        let (stacks, instructions) = parse_input(EXAMPLE.replace("move 1 from 2 to 1", "move 300 from 2 to 1")).unwrap();
+        let result = apply_instructions(stacks, &instructions, &Crane::CrateMover9001, Validation::Strict, false);
+        assert_eq!(result.unwrap_err(), "line 6: move 300 from 2 to 1: stack 2 only holds 3 crates");
+    }
+
+    #[test]
+    fn test_wide_drawings() {
+        let input = String::from("[AB]          [X]
+[C]  [DEF]    [Y]  [Z]
+ 1    2   3    4    5
+");
+        let (stacks, _) = parse_input(input).unwrap();
+        assert_eq!(stacks, vec![vec!["C", "AB"], vec!["DEF"], vec![], vec!["Y", "X"], vec!["Z"]]);
+        assert_eq!(crates_at_top_of_stacks(&stacks).to_string(), "[AB] [DEF] [] [X] [Z]");
+
+        let tops = |ids: &[Option<&str>]| TopCrates(ids.iter().map(|id| id.map(String::from)).collect()).to_string();
+        assert_eq!(tops(&[Some("C"), None, Some("Z")]), "C_Z");
+        assert_eq!(tops(&[Some("C"), None, Some("_")]), "[C] [] [_]");
+
+        let stacks: Stacks = (1..=12).map(|i| (0..i % 3).map(|j| format!("{}{}", i, j)).collect()).collect();
+        let drawing = draw_stacks(&stacks);
+        assert!(drawing.contains("\n  1     2     3  "));
+        let (parsed, instructions) = parse_input(format!("{}\n\nmove 1 from 11 to 12\n", drawing)).unwrap();
+        assert_eq!(parsed, stacks);
+        assert_eq!(instructions[0].from, 11);
+        let (parsed, _) = parse_input(draw_stacks(&stacks[..3].to_vec())).unwrap();
+        assert_eq!(parsed, stacks[..3].to_vec());
+    }
+
+    #[test]
+    fn test_invalid_drawings() {
+        assert_eq!(parse_input(String::from("[A]\n 1   3\n")).unwrap_err(), "line 2: expected stack 2, found '3'");
+        assert_eq!(parse_input(String::from("[A]\n    [B]\n 1   2\n")).unwrap_err(), "line 1: crate '[A]' is floating above stack 1");
+        assert_eq!(parse_input(String::from("    [B] A\n 1   2\n")).unwrap_err(), "line 1: malformed crate 'A' at column 9");
+        assert_eq!(parse_input(String::from("[A][B]\n 1   2\n")).unwrap_err(), "line 1: malformed crate '[A][B]' at column 1");
+        assert_eq!(parse_input(String::from("        [B]\n 1   2\n")).unwrap_err(), "line 1: crate '[B]' at column 9 is not above any stack");
+        assert_eq!(parse_input(String::from(" 1\n\nmove 1 to 1\n")).unwrap_err(), "line 3: malformed instruction 'move 1 to 1'");
+    }
 }
@@ -1,29 +1,187 @@
+use std::fmt;
 use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
 
-fn find_marker<const W: usize>(letters: &Vec<char>) -> Option<usize> {
-    let n = letters.len();
-    let mut start = 0;
-    for i in 1..n {
-        // if within window bounds, pick up where we left off
-        let bound = if start + W > i { start } else { i - W };
-        start = i;
-        for j in bound..i {
-            if !letters[j..i].contains(&letters[i]) {
-                start = j;
-                break;
-            }
+const PACKET_WINDOW: usize = 4;
+const MESSAGE_WINDOW: usize = 14;
+
+// finds every position just after a run of `window` distinct bytes, one byte at a time: the
+// last-seen table tells how far back the current run of distinct bytes reaches
+struct MarkerDetector {
+    window: usize,
+    // position just after the last occurrence of each byte, 0 when not seen yet
+    last_seen: [usize; 256],
+    start: usize,
+    offset: usize,
+}
+
+impl MarkerDetector {
+    fn new(window: usize) -> MarkerDetector {
+        // every position would end an empty run
+        assert!(window > 0, "marker window must not be empty");
+        MarkerDetector { window, last_seen: [0; 256], start: 0, offset: 0 }
+    }
+
+    // whether the stream read so far ends with a marker
+    fn push(&mut self, byte: u8) -> bool {
+        self.offset += 1;
+        self.start = self.start.max(self.last_seen[byte as usize]);
+        self.last_seen[byte as usize] = self.offset;
+        self.offset - self.start >= self.window
+    }
+}
+
+// all the markers of a datastream already in memory
+pub fn find_markers(bytes: &[u8], window: usize) -> Vec<usize> {
+    let mut detector = MarkerDetector::new(window);
+    bytes.iter()
+        .zip(1..)
+        .filter(|&(&byte, _)| detector.push(byte))
+        .map(|(_, offset)| offset)
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    StartOfPacket(usize),
+    StartOfMessage(usize),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::StartOfPacket(offset) => write!(f, "start of packet: {}", offset),
+            Event::StartOfMessage(offset) => write!(f, "start of message: {}", offset),
         }
-        if i - start == W {
-            return Some(i);
+    }
+}
+
+// every marker of the datastream, in the order they end, reading the bytes only as far as the
+// caller iterates
+struct Events<I> {
+    bytes: I,
+    packet: MarkerDetector,
+    message: MarkerDetector,
+    // a start of message ending on the same byte as a start of packet
+    pending: Option<Event>,
+}
+
+// datastreams come as plain bytes, or as the results of reading them
+trait StreamByte {
+    fn into_byte(self) -> io::Result<u8>;
+}
+
+impl StreamByte for u8 {
+    fn into_byte(self) -> io::Result<u8> {
+        Ok(self)
+    }
+}
+
+impl StreamByte for io::Result<u8> {
+    fn into_byte(self) -> io::Result<u8> {
+        self
+    }
+}
+
+impl<I: Iterator> Iterator for Events<I> where I::Item: StreamByte {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        if let Some(event) = self.pending.take() {
+            return Some(Ok(event));
+        }
+        for byte in self.bytes.by_ref() {
+            let byte = match byte.into_byte() {
+                Ok(byte) => byte,
+                Err(error) => return Some(Err(error)),
+            };
+            let offset = self.packet.offset + 1;
+            let packet = self.packet.push(byte);
+            let message = self.message.push(byte);
+            match (packet, message) {
+                (true, true) => {
+                    self.pending = Some(Event::StartOfMessage(offset));
+                    return Some(Ok(Event::StartOfPacket(offset)));
+                }
+                (true, false) => return Some(Ok(Event::StartOfPacket(offset))),
+                (false, true) => return Some(Ok(Event::StartOfMessage(offset))),
+                (false, false) => {}
+            }
         }
+        None
+    }
+}
+
+// e.g. events(reader.bytes(), 4, 14) or events(text.bytes(), 4, 14)
+fn events<I: IntoIterator>(bytes: I, packet_window: usize, message_window: usize) -> Events<I::IntoIter>
+where I::Item: StreamByte {
+    Events {
+        bytes: bytes.into_iter(),
+        packet: MarkerDetector::new(packet_window),
+        message: MarkerDetector::new(message_window),
+        pending: None,
     }
-    None
 }
 
 pub fn solve() {
-    let input = fs::read_to_string("resources/day6.txt").unwrap();
-    let letters: Vec<char> = input.chars().collect();
+    // --signal reads another datastream, e.g. a large generated one
+    let path = crate::flag_values("--signal").pop().unwrap_or_else(|| "resources/day6.txt".to_string());
+    let reader = BufReader::new(File::open(&path).unwrap());
+
+    let mut start_of_packet = None;
+    let mut start_of_message = None;
+    for event in events(reader.bytes(), PACKET_WINDOW, MESSAGE_WINDOW) {
+        match event.unwrap() {
+            Event::StartOfPacket(offset) => start_of_packet = start_of_packet.or(Some(offset)),
+            Event::StartOfMessage(offset) => start_of_message = start_of_message.or(Some(offset)),
+        }
+        if start_of_packet.is_some() && start_of_message.is_some() {
+            break;
+        }
+    }
+
+    println!("{}", Event::StartOfPacket(start_of_packet.unwrap()));
+    println!("{}", Event::StartOfMessage(start_of_message.unwrap()));
+
+    for window in crate::flag_values("--markers") {
+        let window: usize = window.parse().ok()
+            .filter(|&w| w > 0)
+            .unwrap_or_else(|| panic!("invalid window size: {}", window));
+        let bytes = fs::read(&path).unwrap();
+        let markers: Vec<String> = find_markers(&bytes, window).iter().map(|p| p.to_string()).collect();
+        println!("{}-byte markers: {}", window, markers.join(", "));
+    }
+}
 
-    println!("start of packet: {}", find_marker::<4>(&letters).unwrap());
-    println!("start of message: {}", find_marker::<14>(&letters).unwrap());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_markers() {
+        let bytes = b"mjqjpqmgbljsphdztnvjfqwrcgsmlb";
+        assert_eq!(find_markers(bytes, 4)[..3], [7, 8, 9]);
+        assert_eq!(find_markers(bytes, 14)[0], 19);
+        assert_eq!(find_markers(b"aaaa", 1), vec![1, 2, 3, 4]);
+        assert!(find_markers(b"abcabc", 4).is_empty());
+    }
+
+    #[test]
+    fn test_streamed_events() {
+        // plain bytes
+        let found: Vec<Event> = events("abcdefghijklmn".bytes(), 4, 14).map(Result::unwrap).collect();
+        let packets = (4..=14).map(Event::StartOfPacket);
+        let mut expected: Vec<Event> = packets.collect();
+        expected.push(Event::StartOfMessage(14));
+        assert_eq!(found, expected);
+
+        // bytes read from a reader, only up to the first start of message
+        let mut bytes = b"mjqjpqmgbljsphdztnvjfqwrcgsmlb".bytes();
+        let first_message = events(bytes.by_ref(), 4, 14)
+            .map(Result::unwrap)
+            .find(|event| matches!(event, Event::StartOfMessage(_)));
+        assert_eq!(first_message, Some(Event::StartOfMessage(19)));
+        assert_eq!(bytes.count(), 11);
+    }
 }
@@ -1,110 +1,385 @@
 use std::fs;
-use std::str::Lines;
 
 #[derive(Debug, PartialEq)]
 pub struct File {
+    name: String,
     size: u32,
 }
 
+// directories reference their parent and subdirectories by index in the filesystem arena
 #[derive(Debug, PartialEq)]
 pub struct Directory {
+    name: String,
     size: u32,
+    parent: Option<usize>,
     files: Vec<File>,
+    directories: Vec<usize>,
+}
+
+// the root directory is always at index 0
+#[derive(Debug, PartialEq)]
+pub struct FileSystem {
     directories: Vec<Directory>,
 }
 
-fn parse_directory_change(line: &str, lines: &mut Lines<'_>, directory: &mut Directory) -> bool {
-    let name = line.split(" ").last().unwrap();
-    if name == ".." {
-        return true;
+const ROOT: usize = 0;
+
+impl FileSystem {
+    fn new() -> FileSystem {
+        FileSystem { directories: vec![Directory { name: String::from("/"), size: 0, parent: None, files: vec![], directories: vec![] }] }
     }
 
-    let mut newdir = Directory { files: vec![], directories: vec![], size: 0, };
-    parse(lines, &mut newdir);
-    directory.size += newdir.size;
-    directory.directories.push(newdir);
-    false
+    // index of the subdirectory with the given name, created if it wasn't seen yet
+    fn subdirectory(&mut self, dir: usize, name: &str) -> usize {
+        let existing = self.directories[dir].directories.iter()
+            .copied()
+            .find(|&d| self.directories[d].name == name);
+        match existing {
+            Some(d) => d,
+            None => {
+                let d = self.directories.len();
+                self.directories.push(Directory { name: String::from(name), size: 0, parent: Some(dir), files: vec![], directories: vec![] });
+                self.directories[dir].directories.push(d);
+                d
+            },
+        }
+    }
+
+    pub fn path(&self, dir: usize) -> String {
+        let mut names = vec![];
+        let mut current = dir;
+        while let Some(parent) = self.directories[current].parent {
+            names.push(self.directories[current].name.as_str());
+            current = parent;
+        }
+        names.reverse();
+        format!("/{}", names.join("/"))
+    }
 }
 
-fn parse_command(line: &str, lines: &mut Lines<'_>, directory: &mut Directory) -> bool {
-    if line.starts_with("$ ls") {
-        return false;
+// Replays a terminal transcript, keeping the current directory and its ancestors on a stack.
+// Files listed more than once are only counted once.
+pub fn parse_root(input: String) -> FileSystem {
+    let mut filesystem = FileSystem::new();
+    let mut cwd: Vec<usize> = vec![ROOT];
+
+    for line in input.lines() {
+        if let Some(command) = line.strip_prefix("$ ") {
+            match command.strip_prefix("cd ") {
+                Some("/") => { cwd.truncate(1); },
+                Some("..") if cwd.len() > 1 => { cwd.pop(); },
+                Some("..") => {},
+                Some(name) => {
+                    let dir = filesystem.subdirectory(*cwd.last().unwrap(), name);
+                    cwd.push(dir);
+                },
+                // ls: the listing follows
+                None => {},
+            }
+        } else if let Some(name) = line.strip_prefix("dir ") {
+            filesystem.subdirectory(*cwd.last().unwrap(), name);
+        } else {
+            let (size, name) = line.split_once(' ').unwrap();
+            let size: u32 = size.parse().unwrap();
+            let dir = &mut filesystem.directories[*cwd.last().unwrap()];
+            if dir.files.iter().any(|f| f.name == name) {
+                continue;
+            }
+            dir.files.push(File { name: String::from(name), size });
+            dir.size += size;
+        }
+    }
+
+    // subdirectories are always created after their parent, so a reverse scan of the arena sees
+    // every directory's total before adding it to its parent's
+    for d in (1..filesystem.directories.len()).rev() {
+        let parent = filesystem.directories[d].parent.unwrap();
+        filesystem.directories[parent].size += filesystem.directories[d].size;
     }
-    
-    return parse_directory_change(line, lines, directory);
+
+    filesystem
+}
+
+pub fn filtered_sum(filesystem: &FileSystem, max: u32) -> u32 {
+    filesystem.directories.iter()
+        .map(|d| d.size)
+        .filter(|&size| size <= max)
+        .sum()
+}
+
+// smallest directory at least as large as the given size
+pub fn min_greater_than(filesystem: &FileSystem, free: u32) -> Option<usize> {
+    (0..filesystem.directories.len())
+        .filter(|&d| filesystem.directories[d].size >= free)
+        .min_by_key(|&d| filesystem.directories[d].size)
 }
 
-fn parse_file_listing(line: &str, directory: &mut Directory) {
-    let mut file = line.split(" ");
-    let size = u32::from_str_radix(file.next().unwrap(), 10).unwrap();
-    directory.files.push(File { size: size, });
-    directory.size += size;
+// bitsets of freeable totals
+fn has_bit(bits: &[u64], i: usize) -> bool {
+    bits[i / 64] & (1 << (i % 64)) != 0
 }
 
-fn parse_listing(line: &str, directory: &mut Directory) {
-    if line.starts_with("dir") {
-        return;
+// target |= source << shift, dropping whatever goes past the end
+fn or_shifted(target: &mut [u64], source: &[u64], shift: usize) {
+    let (words, bits) = (shift / 64, shift % 64);
+    for i in (words..target.len()).rev() {
+        let mut w = source[i - words] << bits;
+        if bits > 0 && i > words {
+            w |= source[i - words - 1] >> (64 - bits);
+        }
+        target[i] |= w;
     }
+}
+
+// each reach table below takes (directories + 1) bitsets of the largest useful total, and up to
+// four of them are alive at once
+const PLAN_MEMORY_LIMIT: usize = 256 << 20;
 
-    parse_file_listing(line, directory);
+// Directories are laid out in pre-order, so that a subdirectory comes right after its parent and
+// a directory's subtree ends at position end[k]. Walking that list, every directory is either
+// deleted, skipping its whole subtree, or kept and its subtree looked into.
+// reach[k] holds the totals that can be freed by the time position k is reached; since no plan
+// can do worse than min_greater_than's single directory, only totals up to its size are tracked.
+struct Layout {
+    order: Vec<usize>,
+    sizes: Vec<usize>,
+    end: Vec<usize>,
+    words: usize,
 }
 
-fn parse(lines: &mut Lines<'_>, directory: &mut Directory) {
-    match lines.next() {
-        Some(line) => {
-            if line.starts_with('$') {
-                let pop = parse_command(line, lines, directory);
-                if pop { return };
-            } else {
-                parse_listing(line, directory);
+impl Layout {
+    fn new(filesystem: &FileSystem, bound: usize) -> Layout {
+        let dirs = &filesystem.directories;
+        let mut order = vec![];
+        let mut stack = vec![ROOT];
+        while let Some(d) = stack.pop() {
+            order.push(d);
+            stack.extend(dirs[d].directories.iter().rev());
+        }
+        let n = order.len();
+        let mut position = vec![0; dirs.len()];
+        for (k, &d) in order.iter().enumerate() {
+            position[d] = k;
+        }
+        // a subtree ends where the next subtree of its parent starts: going backwards, each
+        // directory's subtree ends where its last subdirectory's does
+        let mut end = vec![0; n];
+        for k in (0..n).rev() {
+            end[k] = match dirs[order[k]].directories.last() {
+                Some(&last) => end[position[last]],
+                None => k + 1,
+            };
+        }
+        let sizes = order.iter().map(|&d| dirs[d].size as usize).collect();
+        Layout { order, sizes, end, words: bound / 64 + 1 }
+    }
+
+    // deleting a directory draws on `deleting`'s totals: the table itself for any number of
+    // deletions, or the one allowing one deletion fewer
+    fn reach(&self, deleting: Option<&[Vec<u64>]>) -> Vec<Vec<u64>> {
+        let n = self.order.len();
+        let bound = self.words * 64 - 1;
+        let mut reach: Vec<Vec<u64>> = vec![vec![0; self.words]; n + 1];
+        reach[0][0] = 1;
+        for k in 0..n {
+            let (done, rest) = reach.split_at_mut(k + 1);
+            or_shifted(&mut rest[0], &done[k], 0);
+            let source = match deleting {
+                Some(fewer) => &fewer[k],
+                None => &done[k],
+            };
+            if self.sizes[k] <= bound {
+                or_shifted(&mut rest[self.end[k] - k - 1], source, self.sizes[k]);
             }
-            parse(lines, directory);
-        },
-        _ => {}
+        }
+        reach
     }
-}
 
-pub fn parse_root(input: String) -> Directory {
-    let mut lines = input.lines();
-    lines.next();
-    let mut root = Directory {
-        files: vec![],
-        directories: vec![],
-        size: 0,
-    }; 
-    parse(&mut lines, &mut root);
-    root
+    // totals freed with at most `deletions` directories
+    fn limited_reach(&self, deletions: usize) -> Vec<Vec<u64>> {
+        let mut reach = {
+            let none = vec![vec![0; self.words]; self.order.len() + 1];
+            self.reach(Some(&none))
+        };
+        for _ in 0..deletions {
+            reach = self.reach(Some(&reach));
+        }
+        reach
+    }
 }
 
-pub fn filtered_sum(dir: &Directory, sum: &mut u32, max: u32) {
-    if dir.size <= max {
-        *sum += dir.size;
+// Smallest total of non-nested directories whose deletion frees at least the needed space, then
+// fewest directories among the plans freeing that total.
+pub fn plan_cleanup(filesystem: &FileSystem, needed: u32) -> Result<Vec<usize>, String> {
+    if needed == 0 {
+        return Ok(vec![]);
+    }
+    let bound = match min_greater_than(filesystem, needed) {
+        Some(dir) => filesystem.directories[dir].size as usize,
+        None => return Err(format!("no directory frees {}", needed)),
+    };
+    let layout = Layout::new(filesystem, bound);
+    let n = layout.order.len();
+    let memory = 4 * (n + 1) * layout.words * 8;
+    if memory > PLAN_MEMORY_LIMIT {
+        return Err(format!("planning needs {} MiB, over the {} MiB limit", memory >> 20, PLAN_MEMORY_LIMIT >> 20));
+    }
+
+    let best = {
+        let reach = layout.reach(None);
+        (needed as usize..=bound).find(|&total| has_bit(&reach[n], total)).unwrap()
+    };
+    // the single directory of min_greater_than is a plan, so this ends
+    let mut deletions = 1;
+    let mut fewer = layout.limited_reach(0);
+    let mut reach = layout.reach(Some(&fewer));
+    while !has_bit(&reach[n], best) {
+        deletions += 1;
+        fewer = reach;
+        reach = layout.reach(Some(&fewer));
+    }
+
+    // walk back from the end: keep a directory whenever the remaining total can still be freed
+    // with the deletions left, otherwise delete one whose subtree ends here
+    let mut plan = vec![];
+    let (mut k, mut total) = (n, best);
+    while total > 0 {
+        if has_bit(&reach[k - 1], total) {
+            k -= 1;
+            continue;
+        }
+        let j = (0..k)
+            .find(|&j| layout.end[j] == k && layout.sizes[j] <= total && has_bit(&fewer[j], total - layout.sizes[j]))
+            .unwrap();
+        plan.push(layout.order[j]);
+        total -= layout.sizes[j];
+        k = j;
+        deletions -= 1;
+        if total > 0 {
+            reach = fewer;
+            fewer = layout.limited_reach(deletions - 1);
+        }
     }
-    for d in &dir.directories{
-        filtered_sum(&d, sum, max);
+    plan.sort_unstable();
+
+    Ok(plan)
+}
+
+// directory at an absolute path such as "/a/e"
+pub fn find_directory(filesystem: &FileSystem, path: &str) -> Option<usize> {
+    path.split('/')
+        .filter(|name| !name.is_empty())
+        .try_fold(ROOT, |dir, name| filesystem.directories[dir].directories.iter()
+                  .copied()
+                  .find(|&d| filesystem.directories[d].name == name))
+}
+
+// total size of every directory under dir, by absolute path, subdirectories listed before their
+// parent
+pub fn disk_usage(filesystem: &FileSystem, dir: usize) -> Vec<(String, u32)> {
+    let mut usage = vec![];
+    let mut stack = vec![dir];
+    while let Some(d) = stack.pop() {
+        usage.push((filesystem.path(d), filesystem.directories[d].size));
+        stack.extend(filesystem.directories[d].directories.iter());
     }
+    usage.reverse();
+    usage
 }
 
-pub fn min_greater_than(dir: &Directory, cumul: &mut u32, free: u32) {
-    if dir.size >= free {
-        if *cumul > dir.size {
-            *cumul = dir.size;
+// same layout as the puzzle's example filesystem
+pub fn print_tree(filesystem: &FileSystem, dir: usize) -> String {
+    enum Entry<'a> {
+        Directory(usize),
+        File(&'a File),
+    }
+
+    let mut out = String::new();
+    let mut stack = vec![(Entry::Directory(dir), 0)];
+    while let Some((entry, depth)) = stack.pop() {
+        let indent = "  ".repeat(depth);
+        match entry {
+            Entry::Directory(d) => {
+                let directory = &filesystem.directories[d];
+                out.push_str(&format!("{indent}- {} (dir, size={})\n", directory.name, directory.size));
+                // pushed in reverse so they come out in order: subdirectories first, then files
+                stack.extend(directory.files.iter().rev().map(|f| (Entry::File(f), depth + 1)));
+                stack.extend(directory.directories.iter().rev().map(|&sd| (Entry::Directory(sd), depth + 1)));
+            },
+            Entry::File(f) => {
+                out.push_str(&format!("{indent}- {} (file, size={})\n", f.name, f.size));
+            },
         }
     }
-    for d in &dir.directories {
-        min_greater_than(&d, cumul, free);
+    out
+}
+
+pub struct Disk {
+    capacity: u32,
+    // free space the update needs
+    required: u32,
+    // largest directory counted by filtered_sum
+    small_directory: u32,
+}
+
+const PUZZLE_DISK: Disk = Disk {
+    capacity: 70000000,
+    required: 30000000,
+    small_directory: 100000,
+};
+
+impl Disk {
+    fn space_to_free(&self, used: u32) -> u32 {
+        self.required.saturating_sub(self.capacity.saturating_sub(used))
     }
 }
 
 pub fn solve() {
     let input = fs::read_to_string("resources/day7.txt").unwrap();
-    let root = parse_root(input);
-    let mut sum: u32 = 0;
-    filtered_sum(&root, &mut sum, 100000);
-    let mut cumul: u32 = root.size;
-    min_greater_than(&root, &mut cumul, 30000000 - (70000000 - root.size));
+    let filesystem = parse_root(input);
+    let disk = Disk {
+        capacity: crate::flag_value("--disk-capacity", PUZZLE_DISK.capacity),
+        required: crate::flag_value("--required-space", PUZZLE_DISK.required),
+        small_directory: crate::flag_value("--small-directory", PUZZLE_DISK.small_directory),
+    };
+
+    let root_size = filesystem.directories[ROOT].size;
+    let needed = disk.space_to_free(root_size);
+    let sum = filtered_sum(&filesystem, disk.small_directory);
     println!("sum: {sum}");
-    println!("directory size: {cumul}");
+    if needed == 0 {
+        println!("nothing to delete");
+    } else if let Some(to_delete) = min_greater_than(&filesystem, needed) {
+        println!("directory size: {}", filesystem.directories[to_delete].size);
+        println!("directory to delete: {}", filesystem.path(to_delete));
+        if std::env::args().any(|arg| arg == "--cleanup-plan") {
+            match plan_cleanup(&filesystem, needed) {
+                Ok(plan) => {
+                    let total: u32 = plan.iter().map(|&d| filesystem.directories[d].size).sum();
+                    let paths: Vec<String> = plan.iter().map(|&d| filesystem.path(d)).collect();
+                    println!("cleanup plan: {} ({} freed)", paths.join(", "), total);
+                },
+                Err(e) => println!("no cleanup plan: {}", e),
+            }
+        }
+    } else {
+        println!("cannot free {needed}: the disk is too small");
+    }
+
+    if std::env::args().any(|arg| arg == "--du") {
+        for (path, size) in disk_usage(&filesystem, ROOT) {
+            println!("{size}\t{path}");
+        }
+    }
+    if std::env::args().any(|arg| arg == "--tree") {
+        print!("{}", print_tree(&filesystem, ROOT));
+    }
+    for path in crate::flag_values("--ls") {
+        match find_directory(&filesystem, &path) {
+            Some(dir) => print!("{}", print_tree(&filesystem, dir)),
+            None => println!("{path}: no such directory"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -128,29 +403,183 @@ $ ls
 50000 f.txt
 "#);
         let actual = parse_root(input);
-        let expected = Directory {
-            files: vec![
-                File { size: 90000, },
-                File { size: 10000, },
-            ],
+        let expected = FileSystem {
             directories: vec![
                 Directory {
+                    name: String::from("/"),
+                    parent: None,
+                    files: vec![
+                        File { name: String::from("c.txt"), size: 90000, },
+                        File { name: String::from("d.txt"), size: 10000, },
+                    ],
+                    directories: vec![1, 2],
+                    size: 160000,
+                },
+                Directory {
+                    name: String::from("a"),
+                    parent: Some(0),
                     files: vec![
-                        File { size: 10000, },
+                        File { name: String::from("e.txt"), size: 10000, },
                     ],
                     directories: vec![],
                     size: 10000,
                 },
                 Directory {
+                    name: String::from("b"),
+                    parent: Some(0),
                     files: vec![
-                        File { size: 50000, },
+                        File { name: String::from("f.txt"), size: 50000, },
                     ],
                     directories: vec![],
                     size: 50000,
                 },
             ],
-            size: 160000,
         };
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_queries() {
+        let input = String::from(r#"$ cd /
+$ ls
+dir a
+14848514 b.txt
+8504156 c.dat
+dir d
+$ cd a
+$ ls
+dir e
+29116 f
+2557 g
+62596 h.lst
+$ cd e
+$ ls
+584 i
+$ cd ..
+$ cd ..
+$ cd d
+$ ls
+4060174 j
+8033020 d.log
+5626152 d.ext
+7214296 k
+"#);
+        let filesystem = parse_root(input);
+        let size = |path| find_directory(&filesystem, path).map(|d| filesystem.directories[d].size);
+        assert_eq!(size("/a/e"), Some(584));
+        assert_eq!(size("/"), Some(48381165));
+        assert_eq!(size("/a/x"), None);
+        assert_eq!(filesystem.path(find_directory(&filesystem, "/a/e").unwrap()), "/a/e");
+
+        let usage = disk_usage(&filesystem, ROOT);
+        let paths: Vec<&str> = usage.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["/a/e", "/a", "/d", "/"]);
+        assert_eq!(usage[1].1, 94853);
+
+        assert_eq!(filtered_sum(&filesystem, 100000), 95437);
+        assert_eq!(min_greater_than(&filesystem, 8381165).map(|d| filesystem.path(d)), Some(String::from("/d")));
+
+        let tree = print_tree(&filesystem, ROOT);
+        assert!(tree.starts_with("- / (dir, size=48381165)\n  - a (dir, size=94853)\n    - e (dir, size=584)\n      - i (file, size=584)\n"));
+    }
+
+    #[test]
+    fn test_messy_transcript() {
+        let input = String::from(r#"$ cd /
+$ cd a
+$ ls
+100 x
+dir b
+$ cd b
+$ ls
+20 y
+$ cd /
+$ ls
+dir a
+3 z
+$ cd a
+$ ls
+100 x
+dir b
+$ cd b
+$ ls
+20 y
+$ cd ..
+$ cd ..
+$ cd ..
+$ ls
+3 z
+"#);
+        let filesystem = parse_root(input);
+        let usage = disk_usage(&filesystem, ROOT);
+        assert_eq!(usage, vec![
+            (String::from("/a/b"), 20),
+            (String::from("/a"), 120),
+            (String::from("/"), 123),
+        ]);
+    }
+
+    #[test]
+    fn test_deep_transcript() {
+        let depth = 100000;
+        let mut input = String::from("$ cd /\n");
+        for _ in 0..depth {
+            input.push_str("$ cd d\n$ ls\n1 f\n");
+        }
+        let filesystem = parse_root(input);
+        assert_eq!(filesystem.directories.len(), depth + 1);
+        assert_eq!(filesystem.directories[ROOT].size, depth as u32);
+        assert_eq!(filtered_sum(&filesystem, 10), 55);
+    }
+
+    #[test]
+    fn test_cleanup_plan() {
+        let input = String::from(r#"$ cd /
+$ ls
+dir x
+dir y
+dir z
+10 r
+$ cd x
+$ ls
+60 f
+$ cd ..
+$ cd z
+$ cd z1
+$ ls
+30 f
+$ cd ..
+$ cd z2
+$ ls
+15 f
+$ cd /
+$ cd y
+$ ls
+50 f
+"#);
+        let filesystem = parse_root(input);
+        let disk = Disk { capacity: 200, required: 105, small_directory: 0 };
+        let needed = disk.space_to_free(filesystem.directories[ROOT].size);
+        assert_eq!(needed, 70);
+        assert_eq!(min_greater_than(&filesystem, needed), Some(ROOT));
+
+        let plan = plan_cleanup(&filesystem, needed).unwrap();
+        let paths: Vec<String> = plan.iter().map(|&d| filesystem.path(d)).collect();
+        assert_eq!(paths, vec!["/x", "/z/z2"]);
+
+        // /z frees as much as /z/z1 and /z/z2 together
+        let plan = plan_cleanup(&filesystem, 45).unwrap();
+        assert_eq!(plan.iter().map(|&d| filesystem.path(d)).collect::<Vec<_>>(), vec!["/z"]);
+
+        assert_eq!(plan_cleanup(&filesystem, 0), Ok(vec![]));
+        assert!(plan_cleanup(&filesystem, 1000).is_err());
+
+        // 3000 nested directories with a 20000 byte file each would need tens of gigabytes
+        let mut input = String::from("$ cd /\n");
+        for _ in 0..3000 {
+            input.push_str("$ ls\n20000 f\ndir d\n$ cd d\n");
+        }
+        let filesystem = parse_root(input);
+        assert!(plan_cleanup(&filesystem, 30000000).err().unwrap().contains("over the 256 MiB limit"));
+    }
 }
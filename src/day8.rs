@@ -5,6 +5,11 @@ fn max_scenic_score(grid: &Vec<Vec<u8>>) -> usize {
     let m = grid.len();
     let n = grid[0].len();
 
+    // every tree is on the edge of the forest, and sees nothing in at least one direction
+    if m < 3 || n < 3 {
+        return 0;
+    }
+
     let west_boundary = 0;
     let east_boundary = n-1;
     let north_boundary = 0;
@@ -42,8 +47,8 @@ fn max_scenic_score(grid: &Vec<Vec<u8>>) -> usize {
     // Compute the traversal direction score by subtracting the index of T from that of the closest 
     // tree larger than it.
     // Keep track of the index of that tree in the respective traversal cache at T's index.
-    for i in north_boundary+1..south_boundary-1 {
-        for j in west_boundary+1..east_boundary-1 {
+    for i in north_boundary+1..south_boundary {
+        for j in west_boundary+1..east_boundary {
             // west to east traversal - start at direct neighbor
             let mut idx = j-1;
             while grid[i][j] > grid[i][idx] && idx != west_boundary {
@@ -138,6 +143,87 @@ fn visible_trees(grid: &Vec<Vec<u8>>) -> HashSet<usize> {
     visible
 }
 
+// what can be seen from a single tree; directions are ordered north, east, south, west
+#[derive(Clone, Debug, PartialEq)]
+struct TreeReport {
+    visible_from: [bool; 4],
+    viewing_distance: [usize; 4],
+    scenic_score: usize,
+}
+
+const DIRECTIONS: [&str; 4] = ["north", "east", "south", "west"];
+
+// visible from outside the forest, viewing distance
+type Sight = (bool, usize);
+
+// sight of each tree of a line of trees, looking towards the start of the line.
+// Keeps a stack of the trees that are not hidden behind a taller one: the closest tree at least as
+// tall as the current one is the first one left after popping the smaller ones.
+fn sweep(line: &[u8]) -> Vec<Sight> {
+    let mut stack: Vec<usize> = vec![];
+    line.iter().enumerate()
+        .map(|(k, &height)| {
+            while let Some(&top) = stack.last() {
+                if line[top] >= height { break; }
+                stack.pop();
+            }
+            let sight = match stack.last() {
+                Some(&blocker) => (false, k - blocker),
+                None => (true, k),
+            };
+            stack.push(k);
+            sight
+        })
+        .collect()
+}
+
+fn sweep_both_ways(line: &[u8]) -> (Vec<Sight>, Vec<Sight>) {
+    let reversed: Vec<u8> = line.iter().rev().copied().collect();
+    let mut backward = sweep(&reversed);
+    backward.reverse();
+    (sweep(line), backward)
+}
+
+fn tree_report(grid: &[Vec<u8>]) -> Vec<Vec<TreeReport>> {
+    let m = grid.len();
+    let n = grid[0].len();
+    let mut report = vec![vec![TreeReport { visible_from: [false; 4], viewing_distance: [0; 4], scenic_score: 0 }; n]; m];
+
+    for (i, row) in grid.iter().enumerate() {
+        let (west, east) = sweep_both_ways(row);
+        for j in 0..n {
+            (report[i][j].visible_from[3], report[i][j].viewing_distance[3]) = west[j];
+            (report[i][j].visible_from[1], report[i][j].viewing_distance[1]) = east[j];
+        }
+    }
+    for j in 0..n {
+        let column: Vec<u8> = grid.iter().map(|row| row[j]).collect();
+        let (north, south) = sweep_both_ways(&column);
+        for i in 0..m {
+            (report[i][j].visible_from[0], report[i][j].viewing_distance[0]) = north[i];
+            (report[i][j].visible_from[2], report[i][j].viewing_distance[2]) = south[i];
+        }
+    }
+    for tree in report.iter_mut().flatten() {
+        tree.scenic_score = tree.viewing_distance.iter().product();
+    }
+
+    report
+}
+
+fn print_tree(report: &[Vec<TreeReport>], i: usize, j: usize) {
+    let tree = &report[i][j];
+    let visible_from: Vec<&str> = DIRECTIONS.iter().zip(tree.visible_from)
+        .filter(|(_, visible)| *visible)
+        .map(|(&direction, _)| direction)
+        .collect();
+    let distances: Vec<String> = DIRECTIONS.iter().zip(tree.viewing_distance)
+        .map(|(direction, distance)| format!("{} {}", direction, distance))
+        .collect();
+    println!("tree ({}, {}): visible from [{}], viewing distance {}, scenic score {}",
+             i, j, visible_from.join(", "), distances.join(", "), tree.scenic_score);
+}
+
 fn as_u8(ch: char) -> u8 {
     match ch {
         '0' => 0,
@@ -154,17 +240,116 @@ fn as_u8(ch: char) -> u8 {
     }
 }
 
+// the forest has to be a non-empty rectangle of digits
+fn parse_grid(input: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut grid: Vec<Vec<u8>> = vec![];
+    for (i, line) in input.lines().enumerate() {
+        if let Some(ch) = line.chars().find(|ch| !ch.is_ascii_digit()) {
+            return Err(format!("line {}: unexpected character '{}'", i + 1, ch));
+        }
+        let row: Vec<u8> = line.chars().map(as_u8).collect();
+        if let Some(first) = grid.first() {
+            if row.len() != first.len() {
+                return Err(format!("line {}: {} trees, expected {} as on line 1", i + 1, row.len(), first.len()));
+            }
+        }
+        grid.push(row);
+    }
+    match grid.first() {
+        Some(row) if !row.is_empty() => Ok(grid),
+        _ => Err(String::from("empty forest")),
+    }
+}
+
 pub fn solve() {
     let input = fs::read_to_string("resources/day8.txt").unwrap();
-    let grid: Vec<Vec<u8>> = input.lines()
-        .map(|line| line.chars()
-             .map(as_u8)
-             .collect())
-        .collect();
+    let grid = parse_grid(&input).unwrap_or_else(|e| panic!("invalid input: {}", e));
 
     let visible = visible_trees(&grid);
     println!("{}", visible.len());
 
     let score = max_scenic_score(&grid);
     println!("{}", score);
+
+    if std::env::args().any(|arg| arg == "--tree-report") {
+        let report = tree_report(&grid);
+        let (i, j) = (0..grid.len())
+            .flat_map(|i| (0..grid[i].len()).map(move |j| (i, j)))
+            .max_by_key(|&(i, j)| report[i][j].scenic_score)
+            .unwrap();
+        print_tree(&report, i, j);
+        for (d, direction) in DIRECTIONS.iter().enumerate() {
+            let count = report.iter().flatten().filter(|tree| tree.visible_from[d]).count();
+            println!("{} trees visible from the {}", count, direction);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // O(n·m·(n+m)): walk away from every tree in every direction
+    fn naive_tree_report(grid: &[Vec<u8>]) -> Vec<Vec<TreeReport>> {
+        let m = grid.len() as i64;
+        let n = grid[0].len() as i64;
+        let steps = [(-1, 0), (0, 1), (1, 0), (0, -1)];
+        (0..m).map(|i| (0..n).map(|j| {
+            let height = grid[i as usize][j as usize];
+            let mut visible_from = [true; 4];
+            let mut viewing_distance = [0; 4];
+            for (d, (di, dj)) in steps.iter().enumerate() {
+                let (mut y, mut x) = (i + di, j + dj);
+                while y >= 0 && x >= 0 && y < m && x < n {
+                    viewing_distance[d] += 1;
+                    if grid[y as usize][x as usize] >= height {
+                        visible_from[d] = false;
+                        break;
+                    }
+                    y += di;
+                    x += dj;
+                }
+            }
+            TreeReport { visible_from, viewing_distance, scenic_score: viewing_distance.iter().product() }
+        }).collect()).collect()
+    }
+
+    #[test]
+    fn test_tree_report_matches_naive() {
+        let example = parse_grid("30373\n25512\n65332\n33549\n35390\n").unwrap();
+        let report = tree_report(&example);
+        assert_eq!(report, naive_tree_report(&example));
+        assert_eq!(report[3][2].viewing_distance, [2, 2, 1, 2]);
+        assert_eq!(report[3][2].scenic_score, 8);
+
+        for (m, n) in [(5, 5), (7, 11), (13, 6), (40, 40)] {
+            let grid: Vec<Vec<u8>> = (0..m)
+                .map(|i| (0..n).map(|j| ((i * 7 + j * 13 + i * j) % 10) as u8).collect())
+                .collect();
+            let report = tree_report(&grid);
+            assert_eq!(report, naive_tree_report(&grid));
+            let visible = report.iter().flatten().filter(|t| t.visible_from.contains(&true)).count();
+            assert_eq!(visible, visible_trees(&grid).len());
+            assert_eq!(report.iter().flatten().map(|t| t.scenic_score).max().unwrap(), max_scenic_score(&grid), "{} {}", m, n);
+        }
+    }
+
+    #[test]
+    fn test_small_forests() {
+        for input in ["7", "12321", "1\n2\n3\n2\n1", "12\n34", "989\n858", "98\n51\n77"] {
+            let grid = parse_grid(input).unwrap();
+            let report = naive_tree_report(&grid);
+            assert_eq!(tree_report(&grid), report);
+            assert_eq!(visible_trees(&grid).len(), grid.len() * grid[0].len());
+            assert_eq!(max_scenic_score(&grid), 0);
+        }
+    }
+
+    #[test]
+    fn test_invalid_forests() {
+        assert_eq!(parse_grid("123\n45\n678"), Err(String::from("line 2: 2 trees, expected 3 as on line 1")));
+        assert_eq!(parse_grid("123\n4a6"), Err(String::from("line 2: unexpected character 'a'")));
+        assert!(parse_grid("").is_err());
+        assert!(parse_grid("\n\n").is_err());
+    }
 }
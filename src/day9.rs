@@ -1,5 +1,6 @@
 use std::fs;
 use std::collections::HashSet;
+use std::fmt;
 use std::ops;
 
 #[derive(Debug)]
@@ -8,6 +9,10 @@ enum Direction {
     Left,
     Up,
     Down,
+    UpRight,
+    UpLeft,
+    DownRight,
+    DownLeft,
 }
 
 #[derive(Debug)]
@@ -16,6 +21,22 @@ struct Move {
     steps: i32,
 }
 
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Right => "R",
+            Direction::Left => "L",
+            Direction::Up => "U",
+            Direction::Down => "D",
+            Direction::UpRight => "UR",
+            Direction::UpLeft => "UL",
+            Direction::DownRight => "DR",
+            Direction::DownLeft => "DL",
+        };
+        write!(f, "{} {}", direction, self.steps)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 struct Coordinates {
     x: i32,
@@ -59,6 +80,10 @@ fn parse_move(line: &str) -> Move {
         "L" => Move { direction: Direction::Left, steps: steps, },
         "U" => Move { direction: Direction::Up, steps: steps, },
         "D" => Move { direction: Direction::Down, steps: steps, },
+        "UR" => Move { direction: Direction::UpRight, steps },
+        "UL" => Move { direction: Direction::UpLeft, steps },
+        "DR" => Move { direction: Direction::DownRight, steps },
+        "DL" => Move { direction: Direction::DownLeft, steps },
         _   => panic!("unrecognized direction: {:?}", direction),
     }
 }
@@ -69,51 +94,205 @@ fn compute_head_step(direction: &Direction) -> Coordinates {
         Direction::Left => Coordinates { x: -1, y: 0 },
         Direction::Up => Coordinates { x: 0, y: 1 },
         Direction::Down => Coordinates { x: 0, y: -1 },
+        Direction::UpRight => Coordinates { x: 1, y: 1 },
+        Direction::UpLeft => Coordinates { x: -1, y: 1 },
+        Direction::DownRight => Coordinates { x: 1, y: -1 },
+        Direction::DownLeft => Coordinates { x: -1, y: -1 },
     }
 }
 
+// a knot that no longer touches the one ahead of it moves one square towards it, diagonally if
+// needed, until they touch again
 fn compute_knot_step(delta: Coordinates) -> Coordinates {
-    let x_abs = delta.x.abs();
-    let y_abs = delta.y.abs();
-    if x_abs < 2 && y_abs < 2 {
-        Coordinates { x: 0, y: 0 }
-    } else if x_abs == 2 && y_abs <= 1 {
-        Coordinates { x: delta.x / 2, y: delta.y }
-    } else if y_abs == 2 && x_abs <= 1 {
-        Coordinates { x: delta.x, y: delta.y / 2 }
-    } else if y_abs == 2 && x_abs == 2 {
-        Coordinates { x: delta.x / 2, y: delta.y / 2 }
-    } else {
-        panic!("unexpected delta: {:?}", delta);
-    }
-}
-
-fn n_knot_rope(moves: &Vec<Move>, n: usize) -> HashSet<Coordinates> {
-    let mut rope = vec![Coordinates { x: 0, y: 0 }; n];
-    let mut positions = HashSet::new();
-    positions.insert(rope[n-1]);
+    let mut step = Coordinates { x: 0, y: 0 };
+    let mut remaining = delta;
+    while remaining.x.abs() > 1 || remaining.y.abs() > 1 {
+        let unit = Coordinates { x: remaining.x.signum(), y: remaining.y.signum() };
+        step += unit;
+        remaining -= unit;
+    }
+    step
+}
+
+struct RopeSimulation {
+    // position of every knot, head first, before the first step and after each step
+    snapshots: Vec<Vec<Coordinates>>,
+    // squares visited by each knot
+    visited: Vec<HashSet<Coordinates>>,
+}
+
+fn simulate_rope(moves: &[Move], knots: usize) -> RopeSimulation {
+    let mut rope = vec![Coordinates { x: 0, y: 0 }; knots];
+    let mut snapshots = vec![rope.clone()];
+    let mut visited: Vec<HashSet<Coordinates>> = rope.iter().map(|&knot| HashSet::from([knot])).collect();
 
     for mov in moves {
         for _ in 0..mov.steps {
             rope[0] += compute_head_step(&mov.direction);
-            for i in 1..n {
+            for i in 1..knots {
                 let delta = rope[i-1] - rope[i];
                 let knot_step = compute_knot_step(delta);
                 rope[i] += knot_step;
             }
-            positions.insert(rope[n-1]);
+            for (knot, positions) in rope.iter().zip(visited.iter_mut()) {
+                positions.insert(*knot);
+            }
+            snapshots.push(rope.clone());
         }
     }
 
-    positions
+    RopeSimulation { snapshots, visited }
+}
+
+enum Animation {
+    PerMove,
+    PerStep,
+}
+
+// how the puzzle labels knots: H for the head, then 1 to 9, T for the tail of a 2 knot rope
+fn knot_label(i: usize, knots: usize) -> char {
+    match i {
+        0 => 'H',
+        1 if knots == 2 => 'T',
+        1..=9 => char::from_digit(i as u32, 10).unwrap(),
+        _ if i == knots - 1 => 'T',
+        _ => '*',
+    }
+}
+
+// the smallest rectangle covering every knot at every step, as (lower left, upper right)
+fn bounding_box(simulation: &RopeSimulation) -> (Coordinates, Coordinates) {
+    let knots = simulation.snapshots.iter().flatten();
+    let min = knots.clone().fold(Coordinates { x: 0, y: 0 }, |b, k| Coordinates { x: b.x.min(k.x), y: b.y.min(k.y) });
+    let max = knots.fold(Coordinates { x: 0, y: 0 }, |b, k| Coordinates { x: b.x.max(k.x), y: b.y.max(k.y) });
+    (min, max)
+}
+
+// draws either the rope, or the squares visited by the tail when no rope is given; knots closer
+// to the head cover those behind them, and 's' marks the starting square
+fn render_frame(rope: Option<&[Coordinates]>, tail_visited: &HashSet<Coordinates>,
+                bounds: &(Coordinates, Coordinates)) -> String {
+    let (min, max) = bounds;
+    let mut frame = String::new();
+    for y in (min.y..=max.y).rev() {
+        for x in min.x..=max.x {
+            let square = Coordinates { x, y };
+            let c = match rope {
+                Some(knots) => match knots.iter().position(|&k| k == square) {
+                    Some(i) => knot_label(i, knots.len()),
+                    None if x == 0 && y == 0 => 's',
+                    None => '.',
+                },
+                None if x == 0 && y == 0 => 's',
+                None if tail_visited.contains(&square) => '#',
+                None => '.',
+            };
+            frame.push(c);
+        }
+        frame.push('\n');
+    }
+    frame
+}
+
+fn animate(moves: &[Move], simulation: &RopeSimulation, animation: &Animation) -> String {
+    let bounds = bounding_box(simulation);
+    let tail_visited = simulation.visited.last().unwrap();
+    let mut frames = vec![format!("== Initial State ==\n\n{}", render_frame(Some(&simulation.snapshots[0]), tail_visited, &bounds))];
+
+    let mut step = 0;
+    for mov in moves {
+        let first = step + 1;
+        step += mov.steps as usize;
+        let shown = match animation {
+            Animation::PerMove => step..=step,
+            Animation::PerStep => first..=step,
+        };
+        for i in shown {
+            let snapshot = &simulation.snapshots[i];
+            let header = match animation {
+                Animation::PerMove => format!("== {} ==", mov),
+                Animation::PerStep => format!("== {} ({}/{}) ==", mov, i + 1 - first, mov.steps),
+            };
+            frames.push(format!("{}\n\n{}", header, render_frame(Some(snapshot), tail_visited, &bounds)));
+        }
+    }
+
+    frames.push(format!("== Tail Visits ==\n\n{}", render_frame(None, tail_visited, &bounds)));
+    frames.join("\n")
 }
 
 pub fn solve() {
     let input = fs::read_to_string("resources/day9.txt").unwrap();
     let moves = input.lines().map(parse_move).collect::<Vec<Move>>();
-    let tail_positions = n_knot_rope(&moves, 2);
-    println!("{}", tail_positions.len());
+    let simulation = simulate_rope(&moves, 2);
+    println!("{}", simulation.visited[1].len());
+
+    let simulation = simulate_rope(&moves, 10);
+    println!("{}", simulation.visited[9].len());
 
-    let tail_positions = n_knot_rope(&moves, 10);
-    println!("{}", tail_positions.len());
+    let animation = match crate::flag_values("--animate").last().map(String::as_str) {
+        Some("move") => Some(Animation::PerMove),
+        Some("step") => Some(Animation::PerStep),
+        Some(mode) => panic!("unknown animation mode: {} (expected move or step)", mode),
+        None => None,
+    };
+    let mut knot_counts = crate::flag_values("--knots");
+    if animation.is_some() && knot_counts.is_empty() {
+        knot_counts.push(String::from("10"));
+    }
+
+    for knots in knot_counts {
+        let knots: usize = knots.parse().ok().filter(|&k| k > 0)
+            .unwrap_or_else(|| panic!("invalid knot count: {}", knots));
+        let simulation = simulate_rope(&moves, knots);
+        let last = simulation.snapshots.last().unwrap();
+        println!("{} knots, {} steps:", knots, simulation.snapshots.len() - 1);
+        for (i, positions) in simulation.visited.iter().enumerate() {
+            println!("knot {}: visited {} squares, ended at ({}, {})", i, positions.len(), last[i].x, last[i].y);
+        }
+        if let Some(animation) = &animation {
+            println!("{}", animate(&moves, &simulation, animation));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rope_simulation() {
+        let input = "R 5\nU 8\nL 8\nD 3\nR 17\nD 10\nL 25\nU 20\n";
+        let moves: Vec<Move> = input.lines().map(parse_move).collect();
+        let simulation = simulate_rope(&moves, 10);
+        assert_eq!(simulation.visited[9].len(), 36);
+        assert_eq!(simulation.snapshots.len(), 1 + 5 + 8 + 8 + 3 + 17 + 10 + 25 + 20);
+
+        let moves: Vec<Move> = "UR 3\nDL 1\n".lines().map(parse_move).collect();
+        let simulation = simulate_rope(&moves, 3);
+        let expected: Vec<Coordinates> = [(2, 2), (2, 2), (1, 1)].iter()
+            .map(|&(x, y)| Coordinates { x, y })
+            .collect();
+        assert_eq!(simulation.snapshots.last().unwrap(), &expected);
+        assert_eq!(simulation.visited[2].len(), 2);
+    }
+
+    #[test]
+    fn test_knot_catches_up_from_afar() {
+        assert_eq!(compute_knot_step(Coordinates { x: 5, y: -2 }), Coordinates { x: 4, y: -2 });
+        assert_eq!(compute_knot_step(Coordinates { x: -1, y: 1 }), Coordinates { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn test_animation_frames() {
+        let moves: Vec<Move> = "R 4\nU 4\nL 3\nD 1\nR 4\nD 1\nL 5\nR 2\n".lines().map(parse_move).collect();
+        let simulation = simulate_rope(&moves, 2);
+        let frames = animate(&moves, &simulation, &Animation::PerMove);
+        assert!(frames.starts_with("== Initial State ==\n\n......\n......\n......\n......\nH.....\n"));
+        assert!(frames.contains("== U 4 ==\n\n....H.\n....T.\n......\n......\ns.....\n"));
+        assert!(frames.ends_with("== Tail Visits ==\n\n..##..\n...##.\n.####.\n....#.\ns###..\n"));
+
+        let frames = animate(&moves, &simulation, &Animation::PerStep);
+        assert_eq!(frames.matches("== R 4 (").count(), 8);
+    }
 }
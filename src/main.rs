@@ -19,6 +19,26 @@ mod day14;
 mod day15;
 mod day16;
 
+use std::env;
+use std::str::FromStr;
+
+// every value passed after `flag` on the command line, e.g. `--rounds 20 --rounds 1000`
+pub fn flag_values(flag: &str) -> Vec<String> {
+    let args: Vec<String> = env::args().collect();
+    args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].clone())
+        .collect()
+}
+
+// the last value passed after `flag`, or the default when there is none
+pub fn flag_value<T: FromStr>(flag: &str, default: T) -> T {
+    match flag_values(flag).last() {
+        Some(v) => v.parse().unwrap_or_else(|_| panic!("invalid {} value: {}", flag, v)),
+        None => default,
+    }
+}
+
 fn main() {
     println!("=================================================================================");
     println!("Day 1:");